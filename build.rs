@@ -1,14 +1,27 @@
 #[path ="src/lib.rs"]
 mod lib;
 
+/// Renders all stages in memory, so invalid configs fail compilation. The
+/// files are only written with the `generate` feature, otherwise that is up
+/// to the binary.
 fn main() {
     use build_script::*;
 
     cargo_rerun_if_changed("gen");
+    // a missing path would rerun the script on every build
+    if std::path::Path::new("plugins").exists() {
+        cargo_rerun_if_changed("plugins");
+    }
     cargo_rerun_if_changed("src");
-    cargo_rerun_if_changed("clusters");
     cargo_rerun_if_changed("Cargo.toml");
     cargo_rerun_if_changed("Cargo.lock");
 
-    lib::build();
+    let result = match std::env::var_os("CARGO_FEATURE_GENERATE") {
+        Some(_) => lib::build(),
+        None => lib::render(),
+    };
+    if let Err(diagnostics) = result {
+        eprintln!("{diagnostics}");
+        std::process::exit(1);
+    }
 }
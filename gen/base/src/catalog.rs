@@ -0,0 +1,232 @@
+//! Static description of the apps and how they are exposed.
+
+use std::fmt::Display;
+
+use crate::{
+    consts, data, data::SecretRef, params::Facts, AppConfig, Apps, ClusterConfig, ServiceMapping,
+};
+
+#[derive(Clone)]
+pub struct App {
+    pub name: &'static str,
+    pub namespace: &'static str,
+    pub port: u16,
+    pub path: &'static str,
+    pub routing: Routing,
+    /// Selects the stage config of the app.
+    pub config: fn(&Apps) -> &AppConfig,
+    /// Environment of the app container.
+    pub env: fn(&Facts) -> Vec<Env>,
+    /// Prometheus endpoint of the app, scraped if metrics are enabled.
+    pub metrics: Option<Metrics>,
+}
+
+#[derive(Clone)]
+pub struct Metrics {
+    pub port: u16,
+    pub path: &'static str,
+}
+
+#[derive(Clone)]
+pub struct Env {
+    pub name: String,
+    pub value: EnvValue,
+}
+
+#[derive(Clone)]
+pub enum EnvValue {
+    Value(String),
+    Secret(SecretRef),
+}
+
+impl Env {
+    pub fn value(name: &str, value: impl Display) -> Env {
+        Env {
+            name: name.to_string(),
+            value: EnvValue::Value(value.to_string()),
+        }
+    }
+
+    pub fn secret(name: &str, secret: SecretRef) -> Env {
+        Env {
+            name: name.to_string(),
+            value: EnvValue::Secret(secret),
+        }
+    }
+}
+
+/// Per-route settings of the ingress. Backends render what they support,
+/// plain Kubernetes Ingress only the path and, on Traefik, the rewrite.
+#[derive(Clone, Default)]
+pub struct Routing {
+    /// Replaces the matched path prefix before the request reaches the app.
+    pub rewrite: Option<String>,
+    pub timeouts: Option<Timeouts>,
+    pub retries: Option<Retries>,
+    pub request_headers: Headers,
+    pub response_headers: Headers,
+    pub health_check: Option<HealthCheck>,
+    pub session_affinity: Option<SessionAffinity>,
+}
+
+#[derive(Clone)]
+pub struct Timeouts {
+    pub connect: String,
+    pub read: String,
+    pub send: String,
+    /// The whole request as seen by the client, retries included.
+    pub request: String,
+}
+
+#[derive(Clone)]
+pub struct Retries {
+    pub attempts: u16,
+    /// Time budget for all attempts together.
+    pub timeout: String,
+}
+
+#[derive(Clone, Default)]
+pub struct Headers {
+    pub set: Vec<(String, String)>,
+    pub remove: Vec<String>,
+}
+
+impl Headers {
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.remove.is_empty()
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: String,
+    pub fails: u16,
+    pub passes: u16,
+}
+
+/// Sticky sessions through a cookie set by the ingress.
+#[derive(Clone)]
+pub struct SessionAffinity {
+    pub cookie: String,
+    pub expires: String,
+}
+
+pub fn frontend() -> App {
+    App {
+        name: consts::apps::frontend::NAME,
+        namespace: consts::apps::NAMESPACE,
+        port: consts::apps::frontend::PORT,
+        path: consts::apps::frontend::PATH,
+        config: |apps| &apps.frontend,
+        env: |_| vec![],
+        metrics: Some(Metrics {
+            port: consts::apps::frontend::METRICS_PORT,
+            path: "/metrics",
+        }),
+        routing: Routing {
+            timeouts: Some(Timeouts {
+                connect: "5s".to_string(),
+                read: "30s".to_string(),
+                send: "30s".to_string(),
+                request: "60s".to_string(),
+            }),
+            response_headers: Headers {
+                set: vec![("X-Frame-Options".to_string(), "DENY".to_string())],
+                remove: vec!["X-Powered-By".to_string()],
+            },
+            health_check: Some(HealthCheck {
+                path: "/".to_string(),
+                interval: "10s".to_string(),
+                fails: 3,
+                passes: 1,
+            }),
+            ..Default::default()
+        },
+    }
+}
+
+pub fn backend() -> App {
+    App {
+        name: consts::apps::backend::NAME,
+        namespace: consts::apps::NAMESPACE,
+        port: consts::apps::backend::PORT,
+        path: consts::apps::backend::PATH,
+        config: |apps| &apps.backend,
+        env: data::env,
+        metrics: Some(Metrics {
+            port: consts::apps::backend::METRICS_PORT,
+            path: "/metrics",
+        }),
+        routing: Routing {
+            rewrite: Some("/".to_string()),
+            timeouts: Some(Timeouts {
+                connect: "5s".to_string(),
+                read: "60s".to_string(),
+                send: "60s".to_string(),
+                request: "120s".to_string(),
+            }),
+            retries: Some(Retries {
+                attempts: 3,
+                timeout: "10s".to_string(),
+            }),
+            request_headers: Headers {
+                set: vec![(
+                    "X-Forwarded-Prefix".to_string(),
+                    consts::apps::backend::PATH.to_string(),
+                )],
+                remove: vec![],
+            },
+            health_check: Some(HealthCheck {
+                path: "/healthz".to_string(),
+                interval: "10s".to_string(),
+                fails: 3,
+                passes: 1,
+            }),
+            session_affinity: Some(SessionAffinity {
+                cookie: "backend_session".to_string(),
+                expires: "1h".to_string(),
+            }),
+            ..Default::default()
+        },
+    }
+}
+
+/// Apps enabled in the cluster, in routing order.
+pub fn apps(config: &ClusterConfig) -> Vec<App> {
+    [frontend(), backend()]
+        .into_iter()
+        .filter(|app| (app.config)(&config.manifest.apps).enabled)
+        .collect()
+}
+
+/// Apps enabled according to `facts`, in routing order.
+pub fn enabled(facts: &Facts) -> Vec<App> {
+    [frontend(), backend()]
+        .into_iter()
+        .filter(|app| facts.apps.iter().any(|enabled| enabled.name == app.name))
+        .collect()
+}
+
+impl App {
+    /// PromQL label matchers of the app containers.
+    pub fn selector(&self) -> String {
+        format!(
+            r#"namespace="{}", container="{}""#,
+            self.namespace, self.name
+        )
+    }
+
+    pub fn service(&self, config: &ClusterConfig) -> ServiceMapping {
+        ServiceMapping {
+            namespace: self.namespace.to_string(),
+            name: self.name.to_string(),
+            port: self.port,
+            path: self.path.to_string(),
+            routing: self.routing.clone(),
+            split: (self.config)(&config.manifest.apps)
+                .delivery
+                .split(self.name),
+        }
+    }
+}
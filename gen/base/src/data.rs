@@ -0,0 +1,270 @@
+//! Datastores of the backend: PostgreSQL and Redis, either deployed into
+//! the cluster or provided as external managed services.
+
+use timpl::*;
+
+use crate::{catalog::Env, consts, naming, params::Facts, yaml};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where a datastore of the backend comes from.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Datastore {
+    Disabled,
+    /// Deployed next to the apps with the bitnami chart, sized by the
+    /// parameters of its package.
+    InCluster,
+    /// Managed outside of the cluster.
+    External(Endpoint),
+}
+
+/// External datastore. The secret holding the password is not generated and
+/// has to exist in the namespace of the apps.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub secret: SecretRef,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SecretRef {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Size {
+    pub cpu: String,
+    pub memory: String,
+}
+
+/// Parameters of the PostgreSQL package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Postgres {
+    pub size: Size,
+    /// Size of the data volume, the instance is ephemeral if `None`.
+    pub persistence: Option<String>,
+    pub backup: Option<Backup>,
+}
+
+impl Default for Postgres {
+    /// A small instance with a volume and without backups.
+    fn default() -> Self {
+        Postgres {
+            size: Size {
+                cpu: "250m".to_string(),
+                memory: "256Mi".to_string(),
+            },
+            persistence: Some("8Gi".to_string()),
+            backup: None,
+        }
+    }
+}
+
+/// Periodic `pg_dumpall` into a volume of its own.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Backup {
+    pub schedule: String,
+    pub storage: String,
+}
+
+/// Parameters of the Redis package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Redis {
+    pub size: Size,
+    /// Size of the data volume, the instance is ephemeral if `None`.
+    pub persistence: Option<String>,
+}
+
+impl Default for Redis {
+    /// A small instance with a volume.
+    fn default() -> Self {
+        Redis {
+            size: Size {
+                cpu: "100m".to_string(),
+                memory: "128Mi".to_string(),
+            },
+            persistence: Some("1Gi".to_string()),
+        }
+    }
+}
+
+impl Datastore {
+    pub fn in_cluster(&self) -> bool {
+        matches!(self, Datastore::InCluster)
+    }
+
+    /// Endpoint the backend connects to, `service` and `secret` describe
+    /// the in-cluster instance.
+    fn endpoint(&self, service: &str, port: u16, secret: SecretRef) -> Option<Endpoint> {
+        match self {
+            Datastore::Disabled => None,
+            Datastore::InCluster => Some(Endpoint {
+                host: service.to_string(),
+                port,
+                secret,
+            }),
+            Datastore::External(endpoint) => Some(endpoint.clone()),
+        }
+    }
+}
+
+/// Connection details of the datastores for the backend container.
+pub fn env(facts: &Facts) -> Vec<Env> {
+    let mut res = vec![];
+    let data = &facts.data;
+
+    let postgres = data.postgres.endpoint(
+        consts::data::postgres::NAME,
+        consts::data::postgres::PORT,
+        SecretRef {
+            name: consts::data::postgres::NAME.to_string(),
+            key: "password".to_string(),
+        },
+    );
+    if let Some(endpoint) = postgres {
+        res.push(Env::value("DATABASE_HOST", endpoint.host));
+        res.push(Env::value("DATABASE_PORT", endpoint.port));
+        res.push(Env::value(
+            "DATABASE_NAME",
+            consts::data::postgres::DATABASE,
+        ));
+        res.push(Env::value("DATABASE_USER", consts::data::postgres::USER));
+        res.push(Env::secret("DATABASE_PASSWORD", endpoint.secret));
+    }
+
+    let redis = data.redis.endpoint(
+        &naming::child(consts::data::redis::NAME, "master"),
+        consts::data::redis::PORT,
+        SecretRef {
+            name: consts::data::redis::NAME.to_string(),
+            key: "redis-password".to_string(),
+        },
+    );
+    if let Some(endpoint) = redis {
+        res.push(Env::value("REDIS_HOST", endpoint.host));
+        res.push(Env::value("REDIS_PORT", endpoint.port));
+        res.push(Env::secret("REDIS_PASSWORD", endpoint.secret));
+    }
+
+    res
+}
+
+fn repository(name: &str) -> String {
+    timpl! {
+        apiVersion: source.toolkit.fluxcd.io/v1beta1
+        kind: HelmRepository
+        metadata:
+          name: { name }
+          namespace: { consts::apps::NAMESPACE }
+        spec:
+          interval: { consts::reconsilation::INTERVAL }
+          url: { consts::data::REPOSITORY }
+    }
+}
+
+fn resources(size: &Size) -> String {
+    timpl! {
+        resources:
+          requests:
+            cpu: { yaml::quote(&size.cpu) }
+            memory: { yaml::quote(&size.memory) }
+          limits:
+            memory: { yaml::quote(&size.memory) }
+    }
+}
+
+fn persistence(size: &Option<String>) -> String {
+    timpl! {
+        persistence:
+          enabled: { size.is_some() }
+          {
+              timpl_map_ln!(size.iter(), size, {
+                  size: { yaml::quote(size) }
+              })
+          }
+    }
+}
+
+pub fn postgres(instance: &Postgres) -> Vec<String> {
+    let mut res = vec![];
+
+    res.push(repository(consts::data::postgres::NAME));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { consts::data::postgres::NAME }
+          namespace: { consts::apps::NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { consts::data::postgres::NAME }
+              chart: postgresql
+              version: { consts::data::postgres::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { consts::data::postgres::NAME }
+            auth:
+              username: { consts::data::postgres::USER }
+              database: { consts::data::postgres::DATABASE }
+            primary:
+              {
+                  resources(&instance.size)
+              }
+              {
+                  persistence(&instance.persistence)
+              }
+            backup:
+              enabled: { instance.backup.is_some() }
+              {
+                  timpl_map_ln!(instance.backup.iter(), backup, {
+                      cronjob:
+                        schedule: { yaml::quote(&backup.schedule) }
+                        storage:
+                          size: { yaml::quote(&backup.storage) }
+                  })
+              }
+    });
+
+    res
+}
+
+pub fn redis(instance: &Redis) -> Vec<String> {
+    let mut res = vec![];
+
+    res.push(repository(consts::data::redis::NAME));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { consts::data::redis::NAME }
+          namespace: { consts::apps::NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { consts::data::redis::NAME }
+              chart: redis
+              version: { consts::data::redis::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { consts::data::redis::NAME }
+            architecture: standalone
+            master:
+              {
+                  resources(&instance.size)
+              }
+              {
+                  persistence(&instance.persistence)
+              }
+    });
+
+    res
+}
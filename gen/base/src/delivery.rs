@@ -0,0 +1,176 @@
+//! Progressive delivery of apps: canary releases and blue/green switching.
+
+use crate::{naming, Image};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Delivery {
+    /// A single Deployment receives all traffic.
+    Standard,
+    /// A canary track runs next to the stable one and receives `weight`
+    /// percent of the traffic. Requests matching any of `matches` always
+    /// go to the canary.
+    Canary {
+        image: Image,
+        replicas: u16,
+        #[schemars(range(max = 100))]
+        weight: u8,
+        matches: Vec<Match>,
+    },
+    /// Blue and green track run side by side with their own image, the app
+    /// Service points at `active`. The image of the app itself is unused.
+    BlueGreen {
+        blue: Image,
+        green: Image,
+        active: Color,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Match {
+    Header { name: String, value: String },
+    Cookie { name: String, value: String },
+}
+
+impl Match {
+    /// Name of the condition in NGINX VirtualServer matches.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Match::Header { .. } => "header",
+            Match::Cookie { .. } => "cookie",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Match::Header { name, .. } | Match::Cookie { name, .. } => name,
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            Match::Header { value, .. } | Match::Cookie { value, .. } => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Color {
+    Blue,
+    Green,
+}
+
+impl std::fmt::Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Color::Blue => "blue",
+            Color::Green => "green",
+        })
+    }
+}
+
+/// Second upstream of a route receiving part of its traffic.
+#[derive(Clone)]
+pub struct Split {
+    /// Name of the Service of the second upstream.
+    pub name: String,
+    pub weight: u8,
+    pub matches: Vec<Match>,
+}
+
+/// One Deployment of an app with its own Service.
+pub struct Track {
+    /// Name of the Deployment and Service, also used as `app` label.
+    pub name: String,
+    /// Value of the `track` label, `None` for apps without tracks.
+    pub track: Option<&'static str>,
+    pub replicas: u16,
+    pub image: Image,
+}
+
+impl Delivery {
+    /// Deployments of the app `name`, whose own tracks run `replicas` of
+    /// `image`.
+    pub fn tracks(&self, name: &str, image: &Image, replicas: u16) -> Vec<Track> {
+        match self {
+            Delivery::Standard => vec![Track {
+                name: name.to_string(),
+                track: None,
+                replicas,
+                image: image.clone(),
+            }],
+            Delivery::Canary {
+                image: canary,
+                replicas: canary_replicas,
+                ..
+            } => vec![
+                Track {
+                    name: name.to_string(),
+                    track: Some("stable"),
+                    replicas,
+                    image: image.clone(),
+                },
+                Track {
+                    name: naming::child(name, "canary"),
+                    track: Some("canary"),
+                    replicas: *canary_replicas,
+                    image: canary.clone(),
+                },
+            ],
+            Delivery::BlueGreen { blue, green, .. } => vec![
+                Track {
+                    name: naming::child(name, "blue"),
+                    track: Some("blue"),
+                    replicas,
+                    image: blue.clone(),
+                },
+                Track {
+                    name: naming::child(name, "green"),
+                    track: Some("green"),
+                    replicas,
+                    image: green.clone(),
+                },
+            ],
+        }
+    }
+
+    /// `app` label of the pods behind the main Service of the app `name`.
+    pub fn selector(&self, name: &str) -> String {
+        match self {
+            Delivery::BlueGreen { active, .. } => naming::child(name, &active.to_string()),
+            _ => name.to_string(),
+        }
+    }
+
+    /// Image serving the main Service, `image` unless blue/green picks one.
+    pub fn active_image<'a>(&'a self, image: &'a Image) -> &'a Image {
+        match self {
+            Delivery::BlueGreen {
+                active: Color::Blue,
+                blue,
+                ..
+            } => blue,
+            Delivery::BlueGreen {
+                active: Color::Green,
+                green,
+                ..
+            } => green,
+            _ => image,
+        }
+    }
+
+    /// Traffic split of the ingress route of the app `name`.
+    pub fn split(&self, name: &str) -> Option<Split> {
+        match self {
+            Delivery::Canary {
+                weight, matches, ..
+            } => Some(Split {
+                name: naming::child(name, "canary"),
+                weight: *weight,
+                matches: matches.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
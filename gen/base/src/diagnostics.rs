@@ -0,0 +1,192 @@
+//! Errors and warnings of a render, tied to the stage, the package and the
+//! config value they are about.
+
+use std::fmt::Display;
+
+use crate::{
+    schema::{InvalidConfig, Violation},
+    RenderError, Stage,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    /// The stage cannot be rendered.
+    Error,
+    /// The stage is rendered, but likely not as intended.
+    Warning,
+}
+
+#[derive(Debug)]
+pub enum DiagnosticKind {
+    /// The config does not match its schema.
+    Config(Violation),
+    Render(Box<RenderError>),
+    /// A dependency that is not deployed, assumed to be provided by the
+    /// cluster itself.
+    MissingDependency {
+        dependency: &'static str,
+    },
+    /// A package that is deployed without any resources.
+    EmptyPackage,
+    /// A parameter block no deployed package reads.
+    UnusedParams,
+    /// A problem a package found in its config.
+    Package(String),
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub stage: Option<Stage>,
+    pub package: Option<&'static str>,
+    /// Path of the config value, as in `manifest.apps.frontend.replicas`.
+    pub path: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, kind: DiagnosticKind) -> Self {
+        Diagnostic {
+            severity,
+            kind,
+            stage: None,
+            package: None,
+            path: None,
+        }
+    }
+
+    /// Error of a package.
+    pub fn error(message: impl Display) -> Self {
+        Diagnostic::new(
+            Severity::Error,
+            DiagnosticKind::Package(message.to_string()),
+        )
+    }
+
+    /// Warning of a package.
+    pub fn warning(message: impl Display) -> Self {
+        Diagnostic::new(
+            Severity::Warning,
+            DiagnosticKind::Package(message.to_string()),
+        )
+    }
+
+    pub fn at(self, path: impl Display) -> Self {
+        Diagnostic {
+            path: Some(path.to_string()),
+            ..self
+        }
+    }
+
+    /// Sets stage and package unless they are known already.
+    pub fn context(self, stage: Stage, package: Option<&'static str>) -> Self {
+        Diagnostic {
+            stage: self.stage.or(Some(stage)),
+            package: self.package.or(package),
+            ..self
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+impl Display for DiagnosticKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiagnosticKind::Config(violation) => f.write_str(&violation.message),
+            DiagnosticKind::Render(err) => err.fmt(f),
+            DiagnosticKind::MissingDependency { dependency } => write!(
+                f,
+                "depends on {dependency}, which is not deployed and has to be provided by the cluster"
+            ),
+            DiagnosticKind::EmptyPackage => f.write_str("package has no resources"),
+            DiagnosticKind::UnusedParams => {
+                f.write_str("parameters of a package that is not deployed")
+            }
+            DiagnosticKind::Package(message) => f.write_str(message),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.kind)?;
+        match (self.stage, self.package) {
+            (Some(stage), Some(package)) => write!(f, "\n  --> {stage}/{package}")?,
+            (Some(stage), None) => write!(f, "\n  --> {stage}")?,
+            (None, Some(package)) => write!(f, "\n  --> {package}")?,
+            (None, None) => {}
+        }
+        match &self.path {
+            Some(path) => write!(f, "\n  at {path}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<RenderError> for Diagnostic {
+    /// The diagnostic of a failed package is kept as it is.
+    fn from(err: RenderError) -> Self {
+        let package = match &err {
+            RenderError::Yaml(err) => Some(err.package),
+            RenderError::Mapping(err) => Some(err.package),
+            RenderError::Collision(err) => Some(err.packages.1),
+            RenderError::Transform(_)
+            | RenderError::Rule(_)
+            | RenderError::Graph(_)
+            | RenderError::Package(_) => None,
+        };
+        let path = matches!(err, RenderError::Transform(_)).then(|| "transformers".to_string());
+        match err {
+            RenderError::Package(diagnostic) => *diagnostic,
+            err => Diagnostic {
+                package,
+                path,
+                ..Diagnostic::new(Severity::Error, DiagnosticKind::Render(Box::new(err)))
+            },
+        }
+    }
+}
+
+impl From<InvalidConfig> for Vec<Diagnostic> {
+    fn from(err: InvalidConfig) -> Self {
+        err.violations
+            .into_iter()
+            .map(|violation| {
+                let path = violation.path.clone();
+                Diagnostic::new(Severity::Error, DiagnosticKind::Config(violation)).at(path)
+            })
+            .collect()
+    }
+}
+
+/// All diagnostics of a failed render, at least one of them an error.
+#[derive(Debug)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0
+            .iter()
+            .try_for_each(|diagnostic| writeln!(f, "{diagnostic}"))?;
+        let errors = self.0.iter().filter(|d| d.is_error()).count();
+        write!(
+            f,
+            "rendering failed with {errors} error(s) and {} warning(s)",
+            self.0.len() - errors
+        )
+    }
+}
+
+impl std::error::Error for Diagnostics {}
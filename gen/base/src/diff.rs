@@ -0,0 +1,396 @@
+//! Structural differences between rendered deployments, independent of the
+//! order of resources and mapping keys.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+use serde_yaml::Value;
+
+use crate::{ClusterConfig, Resource};
+
+/// Difference of a single field, `None` if the field does not exist.
+pub struct Field {
+    pub path: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// apiVersion, kind, namespace and name of a resource.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+pub struct Identity {
+    pub api_version: String,
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+pub enum Change {
+    Added(Identity),
+    Removed(Identity),
+    Changed(Identity, Vec<Field>),
+}
+
+/// Differences between two deployments, configs first.
+pub struct StageDiff {
+    pub config: Vec<Field>,
+    pub resources: Vec<Change>,
+}
+
+impl StageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.config.is_empty() && self.resources.is_empty()
+    }
+}
+
+impl Identity {
+    pub(crate) fn of(resource: &Resource) -> Self {
+        Identity {
+            api_version: resource.api_version().to_string(),
+            kind: resource.kind().to_string(),
+            namespace: resource.namespace().map(str::to_string),
+            name: resource.name().to_string(),
+        }
+    }
+}
+
+/// Field level differences of two values. Sequences of mappings that all
+/// have a `name` are matched by name instead of position.
+pub fn values(before: &Value, after: &Value) -> Vec<Field> {
+    let mut res = vec![];
+    compare("", Some(before), Some(after), &mut res);
+    res
+}
+
+/// Path of the value of `key` in the mapping at `path`.
+pub fn key_path(path: &str, key: &Value) -> String {
+    match path.is_empty() {
+        true => inline(key),
+        false => format!("{path}.{}", inline(key)),
+    }
+}
+
+/// Path of the `i`th item of the sequence at `path`, by name if every item
+/// has one.
+pub fn item_path(path: &str, sequence: &[Value], i: usize) -> String {
+    match named(sequence) {
+        Some(names) => format!("{path}[name={}]", names[i].0),
+        None => format!("{path}[{i}]"),
+    }
+}
+
+fn named(sequence: &[Value]) -> Option<Vec<(&str, &Value)>> {
+    sequence
+        .iter()
+        .map(|item| Some((item.get("name")?.as_str()?, item)))
+        .collect()
+}
+
+fn find<'a>(items: &[(&str, &'a Value)], name: &str) -> Option<&'a Value> {
+    items
+        .iter()
+        .find(|(other, _)| *other == name)
+        .map(|(_, item)| *item)
+}
+
+fn compare(path: &str, before: Option<&Value>, after: Option<&Value>, res: &mut Vec<Field>) {
+    match (before, after) {
+        (Some(Value::Mapping(before)), Some(Value::Mapping(after))) => {
+            let keys = before
+                .keys()
+                .chain(after.keys().filter(|key| !before.contains_key(*key)));
+            keys.for_each(|key| {
+                compare(&key_path(path, key), before.get(key), after.get(key), res);
+            });
+        }
+        (Some(Value::Sequence(before)), Some(Value::Sequence(after))) => {
+            match (named(before), named(after)) {
+                (Some(before), Some(after)) => {
+                    let names = before.iter().map(|(name, _)| *name).chain(
+                        after
+                            .iter()
+                            .map(|(name, _)| *name)
+                            .filter(|name| !before.iter().any(|(other, _)| other == name)),
+                    );
+                    names.for_each(|name| {
+                        let item_path = format!("{path}[name={name}]");
+                        compare(&item_path, find(&before, name), find(&after, name), res);
+                    });
+                }
+                _ => (0..before.len().max(after.len())).for_each(|i| {
+                    compare(&format!("{path}[{i}]"), before.get(i), after.get(i), res);
+                }),
+            }
+        }
+        (Some(Value::Tagged(before)), Some(Value::Tagged(after))) if before.tag == after.tag => {
+            compare(path, Some(&before.value), Some(&after.value), res);
+        }
+        (before, after) if before == after => {}
+        (before, after) => res.push(Field {
+            path: path.to_string(),
+            before: before.cloned(),
+            after: after.cloned(),
+        }),
+    }
+}
+
+/// Matches resources by identity and compares their fields.
+pub fn resources(before: &[Resource], after: &[Resource]) -> Vec<Change> {
+    let mut identities = before
+        .iter()
+        .chain(after)
+        .map(Identity::of)
+        .collect::<Vec<_>>();
+    identities.sort();
+    identities.dedup();
+
+    let find = |resources: &[Resource], identity: &Identity| {
+        resources
+            .iter()
+            .find(|resource| Identity::of(resource) == *identity)
+            .map(|resource| resource.value.clone())
+    };
+
+    identities
+        .into_iter()
+        .filter_map(
+            |identity| match (find(before, &identity), find(after, &identity)) {
+                (Some(_), None) => Some(Change::Removed(identity)),
+                (None, Some(_)) => Some(Change::Added(identity)),
+                (Some(before), Some(after)) => {
+                    let fields = values(&before, &after);
+                    (!fields.is_empty()).then_some(Change::Changed(identity, fields))
+                }
+                (None, None) => None,
+            },
+        )
+        .collect()
+}
+
+pub(crate) fn config_value(config: &ClusterConfig) -> Value {
+    serde_yaml::to_value(config).expect("cluster config is serializable")
+}
+
+pub fn stages(
+    before: (&ClusterConfig, &[Resource]),
+    after: (&ClusterConfig, &[Resource]),
+) -> StageDiff {
+    StageDiff {
+        config: values(&config_value(before.0), &config_value(after.0)),
+        resources: resources(before.1, after.1),
+    }
+}
+
+/// Single line rendering of a value.
+fn inline(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => serde_json::to_string(&Inline(value)).unwrap_or_default(),
+    }
+}
+
+/// Serializes tagged enum values as `{tag: value}` for JSON output.
+struct Inline<'a>(&'a Value);
+
+impl Serialize for Inline<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{SerializeMap, SerializeSeq};
+
+        match self.0 {
+            Value::Sequence(sequence) => {
+                let mut seq = serializer.serialize_seq(Some(sequence.len()))?;
+                sequence
+                    .iter()
+                    .try_for_each(|item| seq.serialize_element(&Inline(item)))?;
+                seq.end()
+            }
+            Value::Mapping(mapping) => {
+                let mut map = serializer.serialize_map(Some(mapping.len()))?;
+                mapping.iter().try_for_each(|(key, value)| {
+                    map.serialize_entry(&inline(key), &Inline(value))
+                })?;
+                map.end()
+            }
+            Value::Tagged(tagged) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(&tagged.tag.to_string(), &Inline(&tagged.value))?;
+                map.end()
+            }
+            value => value.serialize(serializer),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => {
+                write!(
+                    f,
+                    "~ {}: {} -> {}",
+                    self.path,
+                    inline(before),
+                    inline(after)
+                )
+            }
+            (None, Some(after)) => write!(f, "+ {}: {}", self.path, inline(after)),
+            (Some(before), None) => write!(f, "- {}: {}", self.path, inline(before)),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} ", self.api_version, self.kind)?;
+        match &self.namespace {
+            Some(namespace) => write!(f, "{namespace}/{}", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(identity) => write!(f, "+ {identity}"),
+            Change::Removed(identity) => write!(f, "- {identity}"),
+            Change::Changed(identity, fields) => {
+                write!(f, "~ {identity}")?;
+                fields
+                    .iter()
+                    .try_for_each(|field| write!(f, "\n    {field}"))
+            }
+        }
+    }
+}
+
+impl Display for StageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "config:")?;
+        self.config
+            .iter()
+            .try_for_each(|field| writeln!(f, "  {field}"))?;
+        writeln!(f, "resources:")?;
+        self.resources
+            .iter()
+            .try_for_each(|change| writeln!(f, "  {change}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn resource(yaml: &str) -> Resource {
+        Resource::parse("app", yaml).expect("valid yaml")
+    }
+
+    fn deployment(image: &str, replicas: u16) -> Resource {
+        resource(&format!(
+            "
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  namespace: apps
+spec:
+  replicas: {replicas}
+  template:
+    spec:
+      containers:
+      - name: sidecar
+        image: sidecar:1
+      - name: app
+        image: {image}
+"
+        ))
+    }
+
+    #[test]
+    fn named_items_are_compared_by_name() {
+        let before = deployment("app:1", 1);
+        let after = resource(
+            "
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  namespace: apps
+spec:
+  replicas: 1
+  template:
+    spec:
+      containers:
+      - name: app
+        image: app:2
+        args: [--verbose]
+      - name: sidecar
+        image: sidecar:1
+",
+        );
+        let fields = values(&before.value, &after.value)
+            .iter()
+            .map(Field::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                "~ spec.template.spec.containers[name=app].image: app:1 -> app:2",
+                r#"+ spec.template.spec.containers[name=app].args: ["--verbose"]"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn unnamed_items_are_compared_by_position() {
+        let before = serde_yaml::from_str("args: [a, b]").expect("valid yaml");
+        let after = serde_yaml::from_str("args: [a]").expect("valid yaml");
+        let fields = values(&before, &after)
+            .iter()
+            .map(Field::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(fields, ["- args[1]: b"]);
+    }
+
+    #[test]
+    fn stage_diff_lists_config_then_resources() {
+        let before = testing::config();
+        let mut after = testing::config();
+        after.cluster = "test-2".to_string();
+        let service = "
+apiVersion: v1
+kind: Service
+metadata:
+  name: app
+  namespace: apps
+";
+        let config_map = "
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: app
+";
+        let diff = stages(
+            (&before, &[deployment("app:1", 1), resource(service)]),
+            (&after, &[deployment("app:1", 2), resource(config_map)]),
+        );
+        let expected = "\
+config:
+  ~ cluster: test -> test-2
+resources:
+  ~ apps/v1 Deployment apps/app
+    ~ spec.replicas: 1 -> 2
+  + v1 ConfigMap app
+  - v1 Service apps/app
+";
+        assert_eq!(diff.to_string(), expected);
+    }
+
+    #[test]
+    fn equal_stages_have_no_diff() {
+        let config = testing::config();
+        let resources = [deployment("app:1", 1)];
+        assert!(stages((&config, &resources), (&config, &resources)).is_empty());
+    }
+}
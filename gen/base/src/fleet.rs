@@ -0,0 +1,177 @@
+//! Rendering of all deployments into memory. Where the result ends up is up
+//! to the writers of [`crate::output`].
+
+use crate::{
+    diagnostics::{Diagnostic, DiagnosticKind, Diagnostics, Severity},
+    naming, schema, Deployment, Resource, Stage,
+};
+
+/// The rendered resources of every stage.
+pub struct RenderedFleet {
+    pub stages: Vec<RenderedStage>,
+}
+
+pub struct RenderedStage {
+    pub stage: Stage,
+    /// Hash of the cluster config the stage was rendered from.
+    pub config_hash: String,
+    /// Flux Kustomizations of the packages, in reconciliation order.
+    #[cfg(feature = "flux-bootstrap")]
+    pub kustomizations: Vec<String>,
+    pub packages: Vec<RenderedPackage>,
+    /// Warnings, errors fail the render.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+pub struct RenderedPackage {
+    pub name: &'static str,
+    pub resources: Vec<Resource>,
+}
+
+impl RenderedStage {
+    pub fn resources(&self) -> impl Iterator<Item = &Resource> {
+        self.packages.iter().flat_map(|package| &package.resources)
+    }
+}
+
+/// Dependencies that are not deployed, packages without resources and
+/// parameter blocks of packages that are not deployed.
+fn warnings(deployment: &Deployment, packages: &[RenderedPackage]) -> Vec<Diagnostic> {
+    let deployed = |name: &str| deployment.packages.iter().any(|p| p.name() == name);
+    let missing = deployment.packages.iter().flat_map(|package| {
+        package
+            .dependencies(&deployment.config)
+            .into_iter()
+            .filter(|dependency| !deployed(dependency))
+            .map(|dependency| {
+                Diagnostic::new(
+                    Severity::Warning,
+                    DiagnosticKind::MissingDependency { dependency },
+                )
+                .context(deployment.config.stage, Some(package.name()))
+            })
+    });
+    let empty = packages
+        .iter()
+        .filter(|package| package.resources.is_empty())
+        .map(|package| {
+            Diagnostic::new(Severity::Warning, DiagnosticKind::EmptyPackage)
+                .context(deployment.config.stage, Some(package.name))
+        });
+
+    let unused = deployment
+        .config
+        .params
+        .keys()
+        .filter(|block| {
+            !deployment
+                .packages
+                .iter()
+                .any(|package| package.params_block() == Some(block.as_str()))
+        })
+        .map(|block| {
+            Diagnostic::new(Severity::Warning, DiagnosticKind::UnusedParams)
+                .at(format!("params.{block}"))
+                .context(deployment.config.stage, None)
+        });
+
+    missing.chain(empty).chain(unused).collect()
+}
+
+/// Renders a stage, or returns all of its diagnostics if any is an error.
+/// `path` only ends up in the Kustomizations of `flux-bootstrap`.
+#[cfg_attr(not(feature = "flux-bootstrap"), allow(unused_variables))]
+fn stage(
+    deployment: &Deployment,
+    schema: &serde_json::Value,
+    path: &str,
+) -> Result<RenderedStage, Vec<Diagnostic>> {
+    let stage = deployment.config.stage;
+    let mut diagnostics = vec![];
+
+    if let Err(err) = schema::check(schema, &deployment.config) {
+        diagnostics.extend(Vec::<Diagnostic>::from(err));
+    }
+    diagnostics.extend(deployment.packages.iter().flat_map(|package| {
+        package
+            .diagnostics(&deployment.config)
+            .into_iter()
+            .map(|diagnostic| diagnostic.context(stage, Some(package.name())))
+    }));
+    #[cfg(feature = "flux-bootstrap")]
+    let kustomizations = deployment
+        .kustomizations(&format!("{path}/{stage}"))
+        .map_err(|err| diagnostics.push(err.into()));
+    let resources = deployment
+        .resources()
+        .map_err(|err| diagnostics.push(err.into()));
+    let mut diagnostics = diagnostics
+        .into_iter()
+        .map(|diagnostic| diagnostic.context(stage, None))
+        .collect::<Vec<_>>();
+
+    let Ok(resources) = resources else {
+        return Err(diagnostics);
+    };
+    #[cfg(feature = "flux-bootstrap")]
+    let Ok(kustomizations) = kustomizations
+    else {
+        return Err(diagnostics);
+    };
+    let packages = deployment
+        .packages
+        .iter()
+        .map(|package| RenderedPackage {
+            name: package.name(),
+            resources: resources
+                .iter()
+                .filter(|resource| resource.package == package.name())
+                .cloned()
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+    diagnostics.extend(warnings(deployment, &packages));
+    let config = serde_json::to_string(&deployment.config).expect("cluster config is serializable");
+
+    match diagnostics.iter().any(Diagnostic::is_error) {
+        true => Err(diagnostics),
+        false => Ok(RenderedStage {
+            stage,
+            config_hash: format!("{:08x}", naming::fnv1a(&config)),
+            #[cfg(feature = "flux-bootstrap")]
+            kustomizations,
+            packages,
+            diagnostics,
+        }),
+    }
+}
+
+/// Validates the config of every deployment against `schema` and renders
+/// it. `path` is the directory of the stages within the Git repository that
+/// Flux reconciles. Every stage is rendered, so a failure reports the
+/// diagnostics of all of them.
+pub fn render(
+    deployments: &[Deployment],
+    schema: &serde_json::Value,
+    path: &str,
+) -> Result<RenderedFleet, Diagnostics> {
+    let results = deployments
+        .iter()
+        .map(|deployment| stage(deployment, schema, path))
+        .collect::<Vec<_>>();
+
+    match results.iter().all(Result::is_ok) {
+        true => Ok(RenderedFleet {
+            stages: results.into_iter().flatten().collect(),
+        }),
+        false => Err(Diagnostics(
+            results
+                .into_iter()
+                .flat_map(|result| match result {
+                    Ok(stage) => stage.diagnostics,
+                    Err(diagnostics) => diagnostics,
+                })
+                .collect(),
+        )),
+    }
+}
@@ -0,0 +1,466 @@
+//! Grafana dashboards and datasources as code, rendered as custom resources
+//! of the grafana-operator.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use timpl::*;
+
+use crate::{catalog, consts, logging, naming, params::Facts, yaml, ServiceType};
+
+/// Label selecting the Grafana instance of the cluster.
+const INSTANCE_LABEL: (&str, &str) = (
+    "dashboards",
+    consts::infrastructure::monitoring::grafana::NAME,
+);
+
+/// Width of the Grafana dashboard grid.
+const GRID_WIDTH: u16 = 24;
+const PANEL_HEIGHT: u16 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DatasourceKind {
+    Prometheus,
+    Loki,
+    Tempo,
+}
+
+impl DatasourceKind {
+    /// Plugin id of the datasource type.
+    fn plugin(&self) -> &'static str {
+        match self {
+            DatasourceKind::Prometheus => "prometheus",
+            DatasourceKind::Loki => "loki",
+            DatasourceKind::Tempo => "tempo",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Datasource {
+    pub name: String,
+    /// Stable id referenced by the panels.
+    pub uid: String,
+    pub kind: DatasourceKind,
+    pub url: String,
+    pub default: bool,
+}
+
+#[derive(Clone)]
+pub struct Dashboard {
+    pub uid: String,
+    pub title: String,
+    pub tags: Vec<String>,
+    pub rows: Vec<Row>,
+}
+
+/// Collapsible row, its panels share the width of the dashboard.
+#[derive(Clone)]
+pub struct Row {
+    pub title: String,
+    pub panels: Vec<Panel>,
+}
+
+#[derive(Clone)]
+pub struct Panel {
+    pub title: String,
+    pub kind: PanelKind,
+    pub datasource: DatasourceKind,
+    pub unit: Option<String>,
+    pub queries: Vec<Query>,
+}
+
+#[derive(Clone, Copy)]
+pub enum PanelKind {
+    TimeSeries,
+    Stat,
+    Logs,
+}
+
+impl PanelKind {
+    fn plugin(&self) -> &'static str {
+        match self {
+            PanelKind::TimeSeries => "timeseries",
+            PanelKind::Stat => "stat",
+            PanelKind::Logs => "logs",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Query {
+    pub expr: String,
+    pub legend: Option<String>,
+}
+
+impl Panel {
+    pub fn new(title: &str, kind: PanelKind, datasource: DatasourceKind) -> Panel {
+        Panel {
+            title: title.to_string(),
+            kind,
+            datasource,
+            unit: None,
+            queries: vec![],
+        }
+    }
+
+    pub fn unit(mut self, unit: &str) -> Panel {
+        self.unit = Some(unit.to_string());
+        self
+    }
+
+    pub fn query(mut self, expr: impl Into<String>, legend: Option<&str>) -> Panel {
+        self.queries.push(Query {
+            expr: expr.into(),
+            legend: legend.map(str::to_string),
+        });
+        self
+    }
+}
+
+impl Dashboard {
+    /// Dashboard model as understood by the Grafana API. Panels are laid
+    /// out row by row, each row split evenly between its panels.
+    pub fn to_json(&self) -> String {
+        let mut panels = vec![];
+        let mut y = 0;
+
+        self.rows.iter().for_each(|row| {
+            panels.push(json!({
+                "id": panels.len() + 1,
+                "type": "row",
+                "title": row.title,
+                "collapsed": false,
+                "gridPos": { "x": 0, "y": y, "w": GRID_WIDTH, "h": 1 },
+            }));
+            y += 1;
+
+            let width = GRID_WIDTH / row.panels.len().max(1) as u16;
+            row.panels.iter().enumerate().for_each(|(j, panel)| {
+                panels.push(panel.to_json(panels.len() + 1, j as u16 * width, y, width));
+            });
+            if !row.panels.is_empty() {
+                y += PANEL_HEIGHT;
+            }
+        });
+
+        let dashboard = json!({
+            "uid": self.uid,
+            "title": self.title,
+            "tags": self.tags,
+            "editable": false,
+            "schemaVersion": 39,
+            "time": { "from": "now-6h", "to": "now" },
+            "panels": panels,
+        });
+        serde_json::to_string_pretty(&dashboard).unwrap()
+    }
+}
+
+impl Panel {
+    fn to_json(&self, id: usize, x: u16, y: u16, width: u16) -> Value {
+        let datasource = json!({
+            "type": self.datasource.plugin(),
+            "uid": self.datasource.plugin(),
+        });
+        let targets = self
+            .queries
+            .iter()
+            .enumerate()
+            .map(|(i, query)| {
+                let mut target = json!({
+                    "refId": char::from(b'A' + i as u8).to_string(),
+                    "datasource": datasource,
+                    "expr": query.expr,
+                });
+                if let Some(legend) = &query.legend {
+                    target["legendFormat"] = json!(legend);
+                }
+                target
+            })
+            .collect::<Vec<_>>();
+
+        let mut panel = json!({
+            "id": id,
+            "type": self.kind.plugin(),
+            "title": self.title,
+            "datasource": datasource,
+            "gridPos": { "x": x, "y": y, "w": width, "h": PANEL_HEIGHT },
+            "targets": targets,
+        });
+        if let Some(unit) = &self.unit {
+            panel["fieldConfig"] = json!({ "defaults": { "unit": unit }, "overrides": [] });
+        }
+        panel
+    }
+}
+
+/// Datasources of the telemetry backends generated for the cluster.
+pub fn datasources(facts: &Facts) -> Vec<Datasource> {
+    use consts::infrastructure::monitoring::{prometheus, tempo, NAMESPACE};
+
+    let mut res = vec![];
+
+    if facts.metrics {
+        res.push(Datasource {
+            name: "Prometheus".to_string(),
+            uid: DatasourceKind::Prometheus.plugin().to_string(),
+            kind: DatasourceKind::Prometheus,
+            url: format!(
+                "http://{}.{NAMESPACE}.svc:{}",
+                prometheus::SERVICE,
+                prometheus::PORT
+            ),
+            default: true,
+        });
+    }
+    if facts.logging {
+        res.push(Datasource {
+            name: "Loki".to_string(),
+            uid: DatasourceKind::Loki.plugin().to_string(),
+            kind: DatasourceKind::Loki,
+            url: logging::url(),
+            default: false,
+        });
+    }
+    if facts.sampling_ratio.is_some() {
+        res.push(Datasource {
+            name: "Tempo".to_string(),
+            uid: DatasourceKind::Tempo.plugin().to_string(),
+            kind: DatasourceKind::Tempo,
+            url: format!(
+                "http://{}.{NAMESPACE}.svc:{}",
+                tempo::NAME,
+                tempo::HTTP_PORT
+            ),
+            default: false,
+        });
+    }
+
+    res
+}
+
+/// Default dashboard of an app, only with rows for the available datasources.
+pub fn app_dashboard(app: &catalog::App, datasources: &[Datasource]) -> Dashboard {
+    let available = |kind| datasources.iter().any(|datasource| datasource.kind == kind);
+    let selector = app.selector();
+    let requests = format!("http_server_request_duration_seconds_count{{{selector}}}");
+    let mut rows = vec![];
+
+    if available(DatasourceKind::Prometheus) {
+        if app.metrics.is_some() {
+            rows.push(Row {
+                title: "Traffic".to_string(),
+                panels: vec![
+                    Panel::new("Requests", PanelKind::TimeSeries, DatasourceKind::Prometheus)
+                        .unit("reqps")
+                        .query(format!("sum by (pod) (rate({requests}[5m]))"), Some("{{pod}}")),
+                    Panel::new("Errors", PanelKind::TimeSeries, DatasourceKind::Prometheus)
+                        .unit("percentunit")
+                        .query(
+                            format!(
+                                r#"sum(rate(http_server_request_duration_seconds_count{{{selector}, http_response_status_code=~"5.."}}[5m])) / sum(rate({requests}[5m]))"#
+                            ),
+                            None,
+                        ),
+                    Panel::new("Latency", PanelKind::TimeSeries, DatasourceKind::Prometheus)
+                        .unit("s")
+                        .query(
+                            format!("histogram_quantile(0.95, sum by (le) (rate(http_server_request_duration_seconds_bucket{{{selector}}}[5m])))"),
+                            Some("p95"),
+                        ),
+                ],
+            });
+        }
+        rows.push(Row {
+            title: "Resources".to_string(),
+            panels: vec![
+                Panel::new("Pods", PanelKind::Stat, DatasourceKind::Prometheus).query(
+                    format!("count(kube_pod_container_info{{{selector}}})"),
+                    None,
+                ),
+                Panel::new("CPU", PanelKind::TimeSeries, DatasourceKind::Prometheus).query(
+                    format!(
+                        "sum by (pod) (rate(container_cpu_usage_seconds_total{{{selector}}}[5m]))"
+                    ),
+                    Some("{{pod}}"),
+                ),
+                Panel::new("Memory", PanelKind::TimeSeries, DatasourceKind::Prometheus)
+                    .unit("bytes")
+                    .query(
+                        format!("sum by (pod) (container_memory_working_set_bytes{{{selector}}})"),
+                        Some("{{pod}}"),
+                    ),
+            ],
+        });
+    }
+    if available(DatasourceKind::Loki) {
+        rows.push(Row {
+            title: "Logs".to_string(),
+            panels: vec![Panel::new("Logs", PanelKind::Logs, DatasourceKind::Loki)
+                .query(format!("{{{selector}}}"), None)],
+        });
+    }
+
+    Dashboard {
+        uid: app.name.to_string(),
+        title: app.name.to_string(),
+        tags: vec![
+            consts::metadata::PART_OF.to_string(),
+            app.namespace.to_string(),
+        ],
+        rows,
+    }
+}
+
+fn instance_selector() -> String {
+    timpl! {
+        instanceSelector:
+          matchLabels:
+            { INSTANCE_LABEL.0 }: { INSTANCE_LABEL.1 }
+    }
+}
+
+pub fn datasource(datasource: &Datasource) -> String {
+    timpl! {
+        apiVersion: grafana.integreatly.org/v1beta1
+        kind: GrafanaDatasource
+        metadata:
+          name: { datasource.uid }
+          namespace: { consts::infrastructure::monitoring::NAMESPACE }
+        spec:
+          {
+              instance_selector()
+          }
+          datasource:
+            name: { datasource.name }
+            uid: { datasource.uid }
+            type: { datasource.kind.plugin() }
+            access: proxy
+            url: { datasource.url }
+            isDefault: { datasource.default }
+    }
+}
+
+pub fn dashboard(dashboard: &Dashboard) -> String {
+    timpl! {
+        apiVersion: grafana.integreatly.org/v1beta1
+        kind: GrafanaDashboard
+        metadata:
+          name: { dashboard.uid }
+          namespace: { consts::infrastructure::monitoring::NAMESPACE }
+        spec:
+          {
+              instance_selector()
+          }
+          json: |
+            {
+                dashboard.to_json()
+            }
+    }
+}
+
+/// The grafana-operator reconciling the custom resources of all namespaces.
+pub fn operator() -> Vec<String> {
+    use consts::infrastructure::monitoring::{grafana, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(timpl! {
+        apiVersion: source.toolkit.fluxcd.io/v1beta1
+        kind: HelmRepository
+        metadata:
+          name: { naming::child(grafana::NAME, "operator") }
+          namespace: { NAMESPACE }
+        spec:
+          interval: { consts::reconsilation::INTERVAL }
+          url: { consts::data::REPOSITORY }
+    });
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { naming::child(grafana::NAME, "operator") }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { naming::child(grafana::NAME, "operator") }
+              chart: grafana-operator
+              version: { grafana::OPERATOR_CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            grafana:
+              enabled: false
+    });
+
+    res
+}
+
+/// Parameters of the Grafana package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GrafanaParams {
+    /// Type of the dashboard Service.
+    pub service_type: ServiceType,
+}
+
+impl Default for GrafanaParams {
+    /// Only reachable through the ingress.
+    fn default() -> Self {
+        GrafanaParams {
+            service_type: ServiceType::ClusterIP,
+        }
+    }
+}
+
+/// Grafana instance serving the dashboards, exposed by the dashboard
+/// Service the ingress routes to.
+pub fn grafana(facts: &Facts, params: &GrafanaParams) -> Vec<String> {
+    use consts::infrastructure::monitoring::{dashboard, grafana, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(timpl! {
+        apiVersion: grafana.integreatly.org/v1beta1
+        kind: Grafana
+        metadata:
+          name: { grafana::NAME }
+          namespace: { NAMESPACE }
+          labels:
+            { INSTANCE_LABEL.0 }: { INSTANCE_LABEL.1 }
+        spec:
+          config:
+            server:
+              root_url: { yaml::quote(format!("%(protocol)s://%(domain)s{}", dashboard::PATH)) }
+              serve_from_sub_path: "true"
+    });
+
+    res.push(timpl! {
+        apiVersion: v1
+        kind: Service
+        metadata:
+          name: { dashboard::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          type: { params.service_type }
+          selector:
+            app: { grafana::NAME }
+          ports:
+          - name: http
+            port: { dashboard::PORT }
+            targetPort: { grafana::PORT }
+    });
+
+    let datasources = datasources(facts);
+    res.extend(datasources.iter().map(datasource));
+    res.extend(
+        catalog::enabled(facts)
+            .iter()
+            .map(|app| dashboard(&app_dashboard(app, &datasources))),
+    );
+
+    res
+}
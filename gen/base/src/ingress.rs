@@ -0,0 +1,295 @@
+use timpl::*;
+
+use crate::{
+    catalog::Routing, consts, diagnostics::Diagnostic, naming, params::Facts, yaml, ServiceMapping,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "cert-manager")]
+pub mod certificates;
+#[cfg(feature = "ingress-gateway-api")]
+pub mod gateway;
+#[cfg(feature = "ingress-nginx")]
+pub mod nginx;
+
+/// Ingress implementation of a cluster, selected per stage.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum IngressBackend {
+    /// NGINX Ingress Controller with `VirtualServer`/`VirtualServerRoute`.
+    /// Active health checks and session cookies need NGINX Plus, without it
+    /// passive health checks and cookie hashing are used instead.
+    #[cfg(feature = "ingress-nginx")]
+    Nginx { plus: bool },
+    /// Plain `networking.k8s.io/v1` Ingress for a controller the cluster
+    /// already runs, e.g. Traefik on k3s.
+    Ingress { class: String },
+    /// Gateway API `Gateway`/`HTTPRoute` served by NGINX Gateway Fabric.
+    #[cfg(feature = "ingress-gateway-api")]
+    GatewayApi,
+}
+
+impl IngressBackend {
+    pub fn backend(&self) -> Box<dyn Backend> {
+        match self {
+            #[cfg(feature = "ingress-nginx")]
+            IngressBackend::Nginx { plus } => Box::new(nginx::Nginx { plus: *plus }),
+            IngressBackend::Ingress { class } => Box::new(Ingress {
+                class: class.clone(),
+            }),
+            #[cfg(feature = "ingress-gateway-api")]
+            IngressBackend::GatewayApi => Box::new(gateway::GatewayApi {}),
+        }
+    }
+}
+
+/// Parameters of the ingress packages, shared by the controller and the
+/// routes so both use the same backend.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct IngressParams {
+    pub backend: IngressBackend,
+    pub tls: bool,
+}
+
+impl Default for IngressParams {
+    /// The ingress controller the cluster already runs.
+    fn default() -> Self {
+        IngressParams {
+            backend: IngressBackend::Ingress {
+                class: consts::infrastructure::ingress::traefik::CLASS.to_string(),
+            },
+            tls: true,
+        }
+    }
+}
+
+/// A domain together with the services routed on it.
+pub struct Host {
+    pub domain: String,
+    /// Base name for the resources generated for this host.
+    pub name: String,
+    pub services: Vec<ServiceMapping>,
+}
+
+/// Every domain of the cluster with all routed services.
+fn hosts(facts: &Facts) -> Vec<Host> {
+    facts
+        .domains
+        .iter()
+        .map(|domain| Host {
+            domain: domain.clone(),
+            name: naming::domain(domain),
+            services: facts.services.clone(),
+        })
+        .collect()
+}
+
+pub trait Backend {
+    /// Resources installing the ingress controller, empty if the cluster
+    /// provides one.
+    fn system(&self, facts: &Facts) -> Vec<String>;
+
+    /// Resources routing every domain to the services.
+    fn routes(&self, facts: &Facts, params: &IngressParams) -> Vec<String>;
+
+    /// HTTP-01 solver answering the ACME challenges of the domains through
+    /// the routes of the backend.
+    #[cfg(feature = "cert-manager")]
+    fn solver(&self) -> String;
+
+    /// Routing of the services the backend cannot express.
+    fn diagnostics(&self, _facts: &Facts) -> Vec<Diagnostic> {
+        vec![]
+    }
+
+    /// Version of the installed controller.
+    fn version(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Regex matching the path of a service with the rest of the path as `$2`,
+/// and the replacement of the match applying the rewrite of the service.
+fn rewrite(service: &ServiceMapping) -> Option<(String, String)> {
+    service.routing.rewrite.as_ref().map(|rewrite| {
+        (
+            format!("^{}(/|$)(.*)", service.path.trim_end_matches('/')),
+            format!("{}/$2", rewrite.trim_end_matches('/')),
+        )
+    })
+}
+
+/// Routing features set by a service, other than the rewrite.
+fn features(routing: &Routing) -> Vec<&'static str> {
+    [
+        ("timeouts", routing.timeouts.is_some()),
+        ("retries", routing.retries.is_some()),
+        ("request headers", !routing.request_headers.is_empty()),
+        ("response headers", !routing.response_headers.is_empty()),
+        ("health check", routing.health_check.is_some()),
+        ("session affinity", routing.session_affinity.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(feature, set)| set.then_some(feature))
+    .collect()
+}
+
+/// Warnings for every routing feature of the services that `backend` only
+/// renders if it is `supported`.
+fn dropped(facts: &Facts, backend: &str, supported: &[&str]) -> Vec<Diagnostic> {
+    facts
+        .services
+        .iter()
+        .flat_map(|service| {
+            features(&service.routing)
+                .into_iter()
+                .filter(|feature| !supported.contains(feature))
+                .map(move |feature| {
+                    Diagnostic::warning(format!(
+                        "{} is routed without its {feature}, which {backend} cannot express",
+                        service.name
+                    ))
+                })
+        })
+        .collect()
+}
+
+pub struct Ingress {
+    pub class: String,
+}
+
+impl Backend for Ingress {
+    fn system(&self, _facts: &Facts) -> Vec<String> {
+        vec![]
+    }
+
+    fn routes(&self, facts: &Facts, params: &IngressParams) -> Vec<String> {
+        let hosts = hosts(facts);
+        let tls = params.tls;
+        let mut res = vec![];
+
+        hosts.iter().for_each(|host| {
+            host.services.iter().for_each(|service| {
+                let name = naming::child(&host.name, &service.name);
+                let rewrite = rewrite(service).filter(|_| self.traefik());
+
+                if let Some((regex, replacement)) = &rewrite {
+                    res.push(timpl! {
+                        apiVersion: traefik.io/v1alpha1
+                        kind: Middleware
+                        metadata:
+                          name: { name }
+                          namespace: { service.namespace }
+                        spec:
+                          replacePathRegex:
+                            regex: { yaml::quote(regex) }
+                            replacement: { yaml::quote(replacement) }
+                    });
+                }
+
+                res.push(timpl! {
+                    apiVersion: networking.k8s.io/v1
+                    kind: Ingress
+                    metadata:
+                      name: { name }
+                      namespace: { service.namespace }
+                      {
+                        timpl_if!(rewrite.is_some(), {
+                            annotations:
+                              traefik.ingress.kubernetes.io/router.middlewares: { service.namespace }-{ name }@kubernetescrd
+                        })
+                      }
+                    spec:
+                      ingressClassName: { self.class }
+                      {
+                        timpl_if!(tls, {
+                            tls:
+                            - hosts:
+                              - { yaml::quote(&host.domain) }
+                              secretName: { naming::child(&host.name, "tls") }
+                        })
+                      }
+                      rules:
+                      - host: { yaml::quote(&host.domain) }
+                        http:
+                          paths:
+                          - path: { service.path }
+                            pathType: Prefix
+                            backend:
+                              service:
+                                name: { service.name }
+                                port:
+                                  number: { service.port }
+                });
+            });
+        });
+
+        #[cfg(feature = "cert-manager")]
+        if tls {
+            hosts.iter().for_each(|host| {
+                let mut namespaces = host
+                    .services
+                    .iter()
+                    .map(|service| service.namespace.as_str())
+                    .collect::<Vec<_>>();
+                namespaces.sort();
+                namespaces.dedup();
+                namespaces.into_iter().for_each(|namespace| {
+                    res.push(certificates::certificate(namespace, host));
+                });
+            });
+        }
+
+        res
+    }
+
+    #[cfg(feature = "cert-manager")]
+    fn solver(&self) -> String {
+        timpl! {
+            ingress:
+              ingressClassName: { self.class }
+        }
+    }
+
+    fn diagnostics(&self, facts: &Facts) -> Vec<Diagnostic> {
+        let services = &facts.services;
+        let mut res = vec![];
+
+        if !self.traefik() {
+            services
+                .iter()
+                .filter(|service| service.routing.rewrite.is_some())
+                .for_each(|service| {
+                    res.push(Diagnostic::error(format!(
+                        "{} rewrites its path, which ingress class {} cannot express",
+                        service.name, self.class
+                    )))
+                });
+        }
+
+        services
+            .iter()
+            .filter_map(|service| service.split.as_ref().map(|split| (service, split)))
+            .for_each(|(service, split)| {
+                res.push(Diagnostic::warning(format!(
+                    "{} gets no traffic, ingress class {} cannot split it from {}",
+                    split.name, self.class, service.name
+                )))
+            });
+
+        res.extend(dropped(
+            facts,
+            &format!("ingress class {}", self.class),
+            &[],
+        ));
+        res
+    }
+}
+
+impl Ingress {
+    /// Whether the class is served by Traefik, which rewrites paths through
+    /// its `Middleware` resources.
+    fn traefik(&self) -> bool {
+        self.class == consts::infrastructure::ingress::traefik::CLASS
+    }
+}
@@ -0,0 +1,114 @@
+//! cert-manager issuing the certificates of the domains through ACME.
+
+use timpl::*;
+
+use super::{Host, IngressBackend, IngressParams};
+use crate::{consts, naming, params::Facts, yaml};
+
+/// cert-manager with its CRDs, solving challenges through Gateway API
+/// routes if the ingress is served by a Gateway.
+pub fn cert_manager(facts: &Facts, params: &IngressParams) -> Vec<String> {
+    use consts::infrastructure::certificates::{CHART_VERSION, NAME, NAMESPACE};
+
+    let mut res = vec![];
+    let metrics = facts.metrics;
+    let gateway_api = gateway_api(&params.backend);
+
+    res.push(timpl! {
+        apiVersion: source.toolkit.fluxcd.io/v1beta1
+        kind: HelmRepository
+        metadata:
+          name: { NAME }
+          namespace: { NAMESPACE }
+        spec:
+          interval: { consts::reconsilation::INTERVAL }
+          url: "https://charts.jetstack.io"
+    });
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { NAME }
+              chart: cert-manager
+              version: { CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            crds:
+              enabled: true
+            prometheus:
+              enabled: { metrics }
+              servicemonitor:
+                enabled: { metrics }
+            {
+                timpl_if!(gateway_api, {
+                    config:
+                      apiVersion: controller.config.cert-manager.io/v1alpha1
+                      kind: ControllerConfiguration
+                      enableGatewayAPI: true
+                })
+            }
+    });
+
+    res
+}
+
+/// Whether the routes are Gateway API resources.
+fn gateway_api(backend: &IngressBackend) -> bool {
+    match backend {
+        #[cfg(feature = "ingress-gateway-api")]
+        IngressBackend::GatewayApi => true,
+        _ => false,
+    }
+}
+
+/// ACME issuer of every certificate, answering HTTP-01 challenges with
+/// `solver`.
+pub fn issuer(solver: String) -> String {
+    use consts::infrastructure::certificates::{ACME_SERVER, EMAIL, ISSUER};
+
+    timpl! {
+        apiVersion: cert-manager.io/v1
+        kind: ClusterIssuer
+        metadata:
+          name: { ISSUER }
+        spec:
+          acme:
+            server: { ACME_SERVER }
+            email: { EMAIL }
+            privateKeySecretRef:
+              name: { naming::child(ISSUER, "account") }
+            solvers:
+            - http01:
+                {
+                    solver
+                }
+    }
+}
+
+/// Certificate of `host` in the secret its routes in `namespace` refer to.
+pub fn certificate(namespace: &str, host: &Host) -> String {
+    let secret = naming::child(&host.name, "tls");
+
+    timpl! {
+        apiVersion: cert-manager.io/v1
+        kind: Certificate
+        metadata:
+          name: { secret }
+          namespace: { namespace }
+        spec:
+          secretName: { secret }
+          dnsNames:
+          - { yaml::quote(&host.domain) }
+          issuerRef:
+            kind: ClusterIssuer
+            name: { consts::infrastructure::certificates::ISSUER }
+    }
+}
@@ -0,0 +1,380 @@
+//! Gateway API `Gateway`/`HTTPRoute` served by NGINX Gateway Fabric.
+
+use timpl::*;
+
+#[cfg(feature = "cert-manager")]
+use super::certificates;
+use super::{dropped, hosts, Backend, Host, IngressParams};
+use crate::{
+    catalog::{Headers, Routing},
+    consts,
+    delivery::Match,
+    diagnostics::Diagnostic,
+    naming,
+    params::Facts,
+    yaml, ServiceMapping,
+};
+
+pub struct GatewayApi {}
+
+impl Backend for GatewayApi {
+    fn system(&self, _facts: &Facts) -> Vec<String> {
+        let mut res = vec![];
+
+        res.push(timpl! {
+            apiVersion: source.toolkit.fluxcd.io/v1
+            kind: GitRepository
+            metadata:
+              name: gateway-api
+              namespace: { consts::flux::NAMESPACE }
+            spec:
+              interval: { consts::reconsilation::INTERVAL }
+              url: "https://github.com/kubernetes-sigs/gateway-api"
+              ref:
+                tag: { consts::infrastructure::ingress::gateway::API_VERSION }
+        });
+
+        res.push(timpl! {
+            apiVersion: kustomize.toolkit.fluxcd.io/v1
+            kind: Kustomization
+            metadata:
+              name: gateway-api
+              namespace: { consts::flux::NAMESPACE }
+            spec:
+              interval: { consts::reconsilation::INTERVAL }
+              path: ./config/crd/standard
+              prune: false
+              wait: true
+              sourceRef:
+                kind: GitRepository
+                name: gateway-api
+        });
+
+        res.push(timpl! {
+            apiVersion: source.toolkit.fluxcd.io/v1beta2
+            kind: HelmRepository
+            metadata:
+              name: { consts::infrastructure::ingress::NAME }
+              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
+            spec:
+              type: oci
+              interval: { consts::reconsilation::INTERVAL }
+              url: "oci://ghcr.io/nginx/charts"
+        });
+
+        res.push(timpl! {
+            apiVersion: helm.toolkit.fluxcd.io/v2beta1
+            kind: HelmRelease
+            metadata:
+              name: { consts::infrastructure::ingress::NAME }
+              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
+            spec:
+              chart:
+                spec:
+                  sourceRef:
+                    kind: HelmRepository
+                    name: { consts::infrastructure::ingress::NAME }
+                  chart: nginx-gateway-fabric
+                  version: { consts::infrastructure::ingress::gateway::CHART_VERSION }
+              interval: { consts::reconsilation::INTERVAL }
+              values:
+                nginxGateway:
+                  gatewayClassName: { consts::infrastructure::ingress::gateway::CLASS }
+        });
+
+        res
+    }
+
+    fn routes(&self, facts: &Facts, params: &IngressParams) -> Vec<String> {
+        let mut res = vec![];
+        let hosts = hosts(facts);
+        let tls = params.tls;
+        let listener = |host: &Host| {
+            if tls {
+                naming::child(&host.name, "https")
+            } else {
+                naming::child(&host.name, "http")
+            }
+        };
+
+        res.push(timpl! {
+            apiVersion: gateway.networking.k8s.io/v1
+            kind: Gateway
+            metadata:
+              name: { consts::infrastructure::ingress::NAME }
+              namespace: { consts::infrastructure::ingress::NAMESPACE }
+            spec:
+              gatewayClassName: { consts::infrastructure::ingress::gateway::CLASS }
+              listeners:
+              {
+                timpl_map_ln!(hosts.iter(), host, {
+                    - name: { naming::child(&host.name, "http") }
+                      hostname: { yaml::quote(&host.domain) }
+                      port: 80
+                      protocol: HTTP
+                      allowedRoutes:
+                        namespaces:
+                          from: All
+                    {
+                        timpl_if!(tls, {
+                            - name: { naming::child(&host.name, "https") }
+                              hostname: { yaml::quote(&host.domain) }
+                              port: 443
+                              protocol: HTTPS
+                              tls:
+                                mode: Terminate
+                                certificateRefs:
+                                - name: { naming::child(&host.name, "tls") }
+                              allowedRoutes:
+                                namespaces:
+                                  from: All
+                        })
+                    }
+                })
+              }
+        });
+
+        hosts.iter().for_each(|host| {
+            if tls {
+                res.push(timpl! {
+                    apiVersion: gateway.networking.k8s.io/v1
+                    kind: HTTPRoute
+                    metadata:
+                      name: { naming::child(&host.name, "redirect") }
+                      namespace: { consts::infrastructure::ingress::NAMESPACE }
+                    spec:
+                      parentRefs:
+                      - name: { consts::infrastructure::ingress::NAME }
+                        namespace: { consts::infrastructure::ingress::NAMESPACE }
+                        sectionName: { naming::child(&host.name, "http") }
+                      hostnames:
+                      - { yaml::quote(&host.domain) }
+                      rules:
+                      - filters:
+                        - type: RequestRedirect
+                          requestRedirect:
+                            scheme: https
+                            statusCode: 301
+                });
+
+                #[cfg(feature = "cert-manager")]
+                res.push(certificates::certificate(
+                    consts::infrastructure::ingress::NAMESPACE,
+                    host,
+                ));
+            }
+
+            host.services.iter().for_each(|service| {
+                res.push(timpl! {
+                    apiVersion: gateway.networking.k8s.io/v1
+                    kind: HTTPRoute
+                    metadata:
+                      name: { naming::child(&host.name, &service.name) }
+                      namespace: { service.namespace }
+                    spec:
+                      parentRefs:
+                      - name: { consts::infrastructure::ingress::NAME }
+                        namespace: { consts::infrastructure::ingress::NAMESPACE }
+                        sectionName: { listener(host) }
+                      hostnames:
+                      - { yaml::quote(&host.domain) }
+                      rules:
+                      {
+                          gateway_rules(service)
+                      }
+                });
+            });
+        });
+
+        res
+    }
+
+    #[cfg(feature = "cert-manager")]
+    fn solver(&self) -> String {
+        timpl! {
+            gatewayHTTPRoute:
+              parentRefs:
+              - name: { consts::infrastructure::ingress::NAME }
+                namespace: { consts::infrastructure::ingress::NAMESPACE }
+                kind: Gateway
+        }
+    }
+
+    fn diagnostics(&self, facts: &Facts) -> Vec<Diagnostic> {
+        dropped(
+            facts,
+            "the Gateway API",
+            &["timeouts", "request headers", "response headers"],
+        )
+    }
+
+    fn version(&self) -> Option<&'static str> {
+        Some(consts::infrastructure::ingress::gateway::CHART_VERSION)
+    }
+}
+
+fn gateway_headers(headers: &Headers) -> String {
+    timpl! {
+        {
+            timpl_if!(!headers.set.is_empty(), {
+                set:
+                {
+                    timpl_map_ln!(headers.set.iter(), header, {
+                        - name: { header.0 }
+                          value: { yaml::quote(&header.1) }
+                    })
+                }
+            })
+        }
+        {
+            timpl_if!(!headers.remove.is_empty(), {
+                remove:
+                {
+                    timpl_map_ln!(headers.remove.iter(), name, {
+                        - { name }
+                    })
+                }
+            })
+        }
+    }
+}
+
+/// Rules of the `HTTPRoute` of a service. With a split, requests matching
+/// one of its conditions go to the split upstream and all other requests are
+/// distributed by weight.
+fn gateway_rules(service: &ServiceMapping) -> String {
+    let mut rules = vec![];
+
+    if let Some(split) = &service.split {
+        split.matches.iter().for_each(|condition| {
+            rules.push(timpl! {
+                - matches:
+                  - path:
+                      type: PathPrefix
+                      value: { service.path }
+                    headers:
+                    {
+                        timpl_if_else!(matches!(condition, Match::Header { .. }), {
+                            - name: { condition.name() }
+                              value: { yaml::quote(condition.value()) }
+                        }, {
+                            - type: RegularExpression
+                              name: Cookie
+                              value: { yaml::quote(format!("(^|;\\s*){}={}(;|$)", escape(condition.name()), escape(condition.value()))) }
+                        })
+                    }
+                  backendRefs:
+                  - name: { split.name }
+                    port: { service.port }
+                  {
+                      gateway_rule(&service.routing)
+                  }
+            });
+        });
+    }
+
+    let weight = service
+        .split
+        .as_ref()
+        .map_or(0, |split| split.weight.min(100));
+
+    rules.push(timpl! {
+        - matches:
+          - path:
+              type: PathPrefix
+              value: { service.path }
+          backendRefs:
+          - name: { service.name }
+            port: { service.port }
+            weight: { 100 - weight }
+          {
+              timpl_map_ln!(service.split.iter(), split, {
+                  - name: { split.name }
+                    port: { service.port }
+                    weight: { weight }
+              })
+          }
+          {
+              gateway_rule(&service.routing)
+          }
+    });
+
+    rules.join("\n")
+}
+
+/// `literal` with the regex metacharacters escaped, so it only matches
+/// itself.
+fn escape(literal: &str) -> String {
+    literal.chars().fold(String::new(), |mut res, char| {
+        if "\\.+*?()|[]{}^$".contains(char) {
+            res.push('\\');
+        }
+        res.push(char);
+        res
+    })
+}
+
+/// Filters and timeouts of an `HTTPRoute` rule. Retries, health checks and
+/// session affinity are not part of the standard Gateway API channel and are
+/// reported as dropped by [`GatewayApi::diagnostics`].
+fn gateway_rule(routing: &Routing) -> String {
+    let mut filters = vec![];
+
+    if let Some(rewrite) = &routing.rewrite {
+        filters.push(timpl! {
+            - type: URLRewrite
+              urlRewrite:
+                path:
+                  type: ReplacePrefixMatch
+                  replacePrefixMatch: { yaml::quote(rewrite) }
+        });
+    }
+
+    if !routing.request_headers.is_empty() {
+        filters.push(timpl! {
+            - type: RequestHeaderModifier
+              requestHeaderModifier:
+                {
+                    gateway_headers(&routing.request_headers)
+                }
+        });
+    }
+
+    if !routing.response_headers.is_empty() {
+        filters.push(timpl! {
+            - type: ResponseHeaderModifier
+              responseHeaderModifier:
+                {
+                    gateway_headers(&routing.response_headers)
+                }
+        });
+    }
+
+    timpl! {
+        {
+            timpl_if!(!filters.is_empty(), {
+                filters:
+                { filters.join("\n") }
+            })
+        }
+        {
+            timpl_map!(routing.timeouts.iter(), timeouts, {
+                timeouts:
+                  request: { timeouts.request }
+                  backendRequest: { timeouts.read }
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_literals_only_match_themselves() {
+        assert_eq!(escape("session_id"), "session_id");
+        assert_eq!(escape("v1.2+beta"), "v1\\.2\\+beta");
+        assert_eq!(escape("a|b(c)"), "a\\|b\\(c\\)");
+    }
+}
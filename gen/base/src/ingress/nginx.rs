@@ -0,0 +1,348 @@
+//! NGINX Ingress Controller with `VirtualServer`/`VirtualServerRoute`.
+
+use timpl::*;
+
+use super::{hosts, rewrite, Backend, IngressParams};
+use crate::{catalog::Routing, consts, monitoring, naming, params::Facts, yaml, ServiceMapping};
+
+pub struct Nginx {
+    pub plus: bool,
+}
+
+impl Backend for Nginx {
+    fn system(&self, facts: &Facts) -> Vec<String> {
+        let mut res = vec![];
+        let metrics = facts.metrics;
+
+        res.push(timpl! {
+            apiVersion: source.toolkit.fluxcd.io/v1beta1
+            kind: HelmRepository
+            metadata:
+              name: { consts::infrastructure::ingress::NAME }
+              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
+            spec:
+              interval: { consts::reconsilation::INTERVAL }
+              url: "https://helm.nginx.com/stable"
+        });
+
+        res.push(timpl! {
+            apiVersion: helm.toolkit.fluxcd.io/v2beta1
+            kind: HelmRelease
+            metadata:
+              name: { consts::infrastructure::ingress::NAME }
+              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
+            spec:
+              chart:
+                spec:
+                  sourceRef:
+                    kind: HelmRepository
+                    name: { consts::infrastructure::ingress::NAME }
+                  chart: nginx-ingress
+                  version: { consts::infrastructure::ingress::CHART_VERSION }
+              interval: { consts::reconsilation::INTERVAL }
+              values:
+                controller:
+                  enableCertManager: { cfg!(feature = "cert-manager") }
+                  name: { consts::infrastructure::ingress::NAME }
+                  ingressClass:
+                    name: { consts::infrastructure::ingress::nginx::CLASS }
+                  enableLatencyMetrics: true
+                  config:
+                    name: { consts::infrastructure::ingress::NAME }
+                  service:
+                    name: { consts::infrastructure::ingress::NAME }
+                  serviceAccount:
+                    name: { consts::infrastructure::ingress::NAME }
+                  reportIngressStatus:
+                    leaderElectionLockName: { naming::child(consts::infrastructure::ingress::NAME, "leader-election") }
+                prometheus:
+                  create: { metrics }
+                  {
+                      timpl_if!(metrics, {
+                          service:
+                            create: true
+                      })
+                  }
+        });
+
+        if metrics {
+            res.push(monitoring::service_monitor(
+                consts::infrastructure::ingress::SYSTEM_NAMESPACE,
+                consts::infrastructure::ingress::NAME,
+                &[(
+                    "app.kubernetes.io/instance",
+                    consts::infrastructure::ingress::NAME,
+                )],
+                "prometheus",
+                "/metrics",
+            ));
+        }
+
+        res
+    }
+
+    fn routes(&self, facts: &Facts, params: &IngressParams) -> Vec<String> {
+        let mut res = vec![];
+        let hosts = hosts(facts);
+        let tls = params.tls;
+
+        hosts.iter().for_each(|host| {
+            res.push(timpl! {
+                apiVersion: k8s.nginx.org/v1
+                kind: VirtualServer
+                metadata:
+                  name: { host.name }
+                  namespace: { consts::infrastructure::ingress::NAMESPACE }
+                spec:
+                  host: { yaml::quote(&host.domain) }
+                  {
+                    timpl_if!(tls, {
+                        tls:
+                          secret: { naming::child(&host.name, "tls") }
+                          {
+                            timpl_if!(cfg!(feature = "cert-manager"), {
+                                cert-manager:
+                                  cluster-issuer: { consts::infrastructure::certificates::ISSUER }
+                            })
+                          }
+                          redirect:
+                            enable: true
+                            code: 301
+                    })
+                  }
+                  routes:
+                  {
+                    timpl_map_ln!(host.services.iter(), service, {
+                        - path: { yaml::quote(Nginx::path(service)) }
+                          route: { service.namespace }/{ naming::child(&host.name, &service.name) }
+                    })
+                  }
+            });
+            host.services.iter().for_each(|service| {
+                let upstreams = Some(&service.name)
+                    .into_iter()
+                    .chain(service.split.iter().map(|split| &split.name))
+                    .collect::<Vec<_>>();
+                res.push(timpl! {
+                    apiVersion: k8s.nginx.org/v1
+                    kind: VirtualServerRoute
+                    metadata:
+                      name: { naming::child(&host.name, &service.name) }
+                      namespace: { service.namespace }
+                    spec:
+                      host: { yaml::quote(&host.domain) }
+                      upstreams:
+                      {
+                          timpl_map_ln!(upstreams.iter(), upstream, {
+                              - name: { upstream }
+                                service: { upstream }
+                                port: { service.port }
+                                {
+                                    self.upstream(&service.routing)
+                                }
+                          })
+                      }
+                      subroutes:
+                      - path: { yaml::quote(Nginx::path(service)) }
+                        {
+                            self.subroute(service)
+                        }
+                });
+            });
+        });
+
+        res
+    }
+
+    #[cfg(feature = "cert-manager")]
+    fn solver(&self) -> String {
+        timpl! {
+            ingress:
+              ingressClassName: { consts::infrastructure::ingress::nginx::CLASS }
+        }
+    }
+
+    fn version(&self) -> Option<&'static str> {
+        Some(consts::infrastructure::ingress::CHART_VERSION)
+    }
+}
+
+impl Nginx {
+    /// Location of a service, a regex if the service rewrites the path since
+    /// `rewritePath` replaces the whole matched location.
+    fn path(service: &ServiceMapping) -> String {
+        match rewrite(service) {
+            Some((regex, _)) => format!("~ {regex}"),
+            None => service.path.clone(),
+        }
+    }
+
+    fn upstream(&self, routing: &Routing) -> String {
+        let mut res = vec![];
+
+        if let Some(timeouts) = &routing.timeouts {
+            res.push(timpl! {
+                connect-timeout: { timeouts.connect }
+                read-timeout: { timeouts.read }
+                send-timeout: { timeouts.send }
+            });
+        }
+
+        if let Some(retries) = &routing.retries {
+            res.push(timpl! {
+                next-upstream: "error timeout http_502 http_503 http_504"
+                next-upstream-tries: { retries.attempts }
+                next-upstream-timeout: { retries.timeout }
+            });
+        }
+
+        if let Some(check) = &routing.health_check {
+            res.push(timpl_if_else!(self.plus, {
+                healthCheck:
+                  enable: true
+                  path: { yaml::quote(&check.path) }
+                  interval: { check.interval }
+                  fails: { check.fails }
+                  passes: { check.passes }
+            }, {
+                max-fails: { check.fails }
+                fail-timeout: { check.interval }
+            }));
+        }
+
+        if let Some(affinity) = &routing.session_affinity {
+            res.push(timpl_if_else!(self.plus, {
+                sessionCookie:
+                  enable: true
+                  name: { affinity.cookie }
+                  expires: { affinity.expires }
+            }, {
+                lb-method: { yaml::quote(format!("hash $cookie_{} consistent", affinity.cookie)) }
+            }));
+        }
+
+        res.join("\n")
+    }
+
+    /// Action of the subroute, distributing traffic if the service has a split.
+    fn subroute(&self, service: &ServiceMapping) -> String {
+        let Some(split) = &service.split else {
+            return timpl! {
+                action:
+                  {
+                      self.action(service, &service.name)
+                  }
+            };
+        };
+        let weight = split.weight.min(100);
+
+        timpl! {
+            {
+                timpl_if!(!split.matches.is_empty(), {
+                    matches:
+                    {
+                        timpl_map_ln!(split.matches.iter(), condition, {
+                            - conditions:
+                              - { condition.kind() }: { condition.name() }
+                                value: { yaml::quote(condition.value()) }
+                              action:
+                                {
+                                    self.action(service, &split.name)
+                                }
+                        })
+                    }
+                })
+            }
+            splits:
+            - weight: { 100 - weight }
+              action:
+                {
+                    self.action(service, &service.name)
+                }
+            - weight: { weight }
+              action:
+                {
+                    self.action(service, &split.name)
+                }
+        }
+    }
+
+    fn action(&self, service: &ServiceMapping, upstream: &str) -> String {
+        let routing = &service.routing;
+
+        if routing.rewrite.is_none()
+            && routing.request_headers.is_empty()
+            && routing.response_headers.is_empty()
+        {
+            return timpl! {
+                pass: { upstream }
+            };
+        }
+
+        // nginx drops request headers that are set to an empty value
+        let request_headers = routing
+            .request_headers
+            .set
+            .iter()
+            .cloned()
+            .chain(
+                routing
+                    .request_headers
+                    .remove
+                    .iter()
+                    .map(|name| (name.clone(), String::new())),
+            )
+            .collect::<Vec<_>>();
+        let response_headers = &routing.response_headers;
+
+        timpl! {
+            proxy:
+              upstream: { upstream }
+              {
+                timpl_map!(rewrite(service).into_iter(), path, {
+                    rewritePath: { yaml::quote(&path.1) }
+                })
+              }
+              {
+                timpl_if!(!request_headers.is_empty(), {
+                    requestHeaders:
+                      pass: true
+                      set:
+                      {
+                        timpl_map_ln!(request_headers.iter(), header, {
+                            - name: { header.0 }
+                              value: { yaml::quote(&header.1) }
+                        })
+                      }
+                })
+              }
+              {
+                timpl_if!(!response_headers.is_empty(), {
+                    responseHeaders:
+                      {
+                        timpl_if!(!response_headers.set.is_empty(), {
+                            add:
+                            {
+                                timpl_map_ln!(response_headers.set.iter(), header, {
+                                    - name: { header.0 }
+                                      value: { yaml::quote(&header.1) }
+                                      always: true
+                                })
+                            }
+                        })
+                      }
+                      {
+                        timpl_if!(!response_headers.remove.is_empty(), {
+                            hide:
+                            {
+                                timpl_map_ln!(response_headers.remove.iter(), name, {
+                                    - { name }
+                                })
+                            }
+                        })
+                      }
+                })
+              }
+        }
+    }
+}
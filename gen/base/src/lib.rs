@@ -1,26 +1,94 @@
 #![allow(clippy::vec_init_then_push)]
 
-use std::fmt::Display;
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
 
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use structstruck::strike as strc;
 
 use timpl::*;
 
+pub mod catalog;
+pub mod data;
+pub mod delivery;
+pub mod diagnostics;
+pub mod diff;
+pub mod fleet;
+#[cfg(feature = "monitoring")]
+pub mod grafana;
+pub mod ingress;
+pub mod logging;
+pub mod metadata;
+pub mod monitoring;
+pub mod naming;
+pub mod output;
+pub mod params;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod provenance;
+pub mod resource;
+pub mod schema;
+pub mod slo;
+#[cfg(test)]
+mod testing;
+pub mod transform;
+pub mod yaml;
+
+use data::Datastore;
+use delivery::Delivery;
+use diagnostics::Diagnostic;
+use params::{Block, Facts, ScopedPackage};
+pub use resource::Resource;
+use slo::{Escalation, Slo};
+use transform::Transformer;
+
+/// Helm chart support, see the `helm-render` feature.
+#[cfg(feature = "helm-render")]
+pub use timpl_gitops_demo_gen_helm as helm;
+
 pub mod consts {
     pub mod reconsilation {
         pub const INTERVAL: &str = "1m";
     }
+    pub mod metadata {
+        pub const DOMAIN: &str = "gitops.example.com";
+        pub const PART_OF: &str = "app";
+        pub const MANAGED_BY: &str = "timpl-gitops-demo";
+    }
+    pub mod flux {
+        pub const NAMESPACE: &str = "flux-system";
+        pub const SOURCE: &str = "flux-system";
+    }
     pub mod apps {
         pub const NAMESPACE: &str = "default";
+        /// Registry of the images of the apps, named like the apps.
+        pub const REGISTRY: &str = "cr.example.com";
         pub mod frontend {
             pub const PATH: &str = "/";
             pub const NAME: &str = "frontend";
             pub const PORT: u16 = 80;
+            pub const METRICS_PORT: u16 = 9100;
         }
         pub mod backend {
             pub const PATH: &str = "/api";
             pub const NAME: &str = "backend";
             pub const PORT: u16 = 80;
+            pub const METRICS_PORT: u16 = 9090;
+        }
+    }
+    pub mod data {
+        pub const REPOSITORY: &str = "https://charts.bitnami.com/bitnami";
+        pub mod postgres {
+            pub const NAME: &str = "postgresql";
+            pub const PORT: u16 = 5432;
+            pub const DATABASE: &str = "backend";
+            pub const USER: &str = "backend";
+            pub const CHART_VERSION: &str = "16.4.5";
+        }
+        pub mod redis {
+            pub const NAME: &str = "redis";
+            pub const PORT: u16 = 6379;
+            pub const CHART_VERSION: &str = "20.6.3";
         }
     }
     pub mod infrastructure {
@@ -28,9 +96,77 @@ pub mod consts {
             pub const NAMESPACE: &str = "ingress";
             pub const SYSTEM_NAMESPACE: &str = "ingress-system";
             pub const NAME: &str = "ingress";
+            pub const CHART_VERSION: &str = "0.15.2";
+            pub mod gateway {
+                pub const CLASS: &str = "nginx";
+                pub const CHART_VERSION: &str = "1.6.2";
+                pub const API_VERSION: &str = "v1.2.1";
+            }
+            pub mod traefik {
+                /// Class of the Traefik ingress controller k3s ships with.
+                pub const CLASS: &str = "traefik";
+            }
+            pub mod nginx {
+                /// Class of the NGINX Ingress Controller.
+                pub const CLASS: &str = "nginx";
+            }
+        }
+        pub mod certificates {
+            pub const NAMESPACE: &str = "cert-manager";
+            pub const NAME: &str = "cert-manager";
+            pub const CHART_VERSION: &str = "v1.16.2";
+            /// ClusterIssuer of the certificates of every domain.
+            pub const ISSUER: &str = "letsencrypt";
+            pub const ACME_SERVER: &str = "https://acme-v02.api.letsencrypt.org/directory";
+            /// Contact of the ACME account, notified about expiring certificates.
+            pub const EMAIL: &str = "platform@example.com";
         }
         pub mod monitoring {
             pub const NAMESPACE: &str = "monitoring";
+            pub mod collector {
+                pub const NAME: &str = "otel-collector";
+                pub const CHART_VERSION: &str = "0.111.2";
+                pub const HTTP_PORT: u16 = 4318;
+            }
+            pub mod tempo {
+                pub const NAME: &str = "tempo";
+                pub const CHART_VERSION: &str = "1.10.3";
+                pub const OTLP_PORT: u16 = 4317;
+                pub const HTTP_PORT: u16 = 3100;
+            }
+            pub mod prometheus {
+                pub const NAME: &str = "kube-prometheus";
+                pub const CHART_VERSION: &str = "10.2.3";
+                /// Service of the Prometheus server created by the chart.
+                pub const SERVICE: &str = "kube-prometheus-prometheus";
+                pub const PORT: u16 = 9090;
+            }
+            pub mod alertmanager {
+                /// Where the secrets of the Alertmanager are mounted.
+                pub const SECRETS: &str = "/etc/alertmanager/secrets";
+                /// Secret with the PagerDuty routing key at `routing-key`,
+                /// provided by the cluster.
+                pub const PAGER_SECRET: &str = "alertmanager-pagerduty";
+                /// Secret with the Slack webhook URL at `url`, provided by the
+                /// cluster.
+                pub const CHAT_SECRET: &str = "alertmanager-slack";
+            }
+            pub mod loki {
+                pub const NAME: &str = "loki";
+                pub const CHART_VERSION: &str = "6.24.0";
+                /// Service in front of all Loki targets.
+                pub const GATEWAY: &str = "loki-gateway";
+                pub const PUSH_PATH: &str = "/loki/api/v1/push";
+            }
+            pub mod promtail {
+                pub const NAME: &str = "promtail";
+                pub const CHART_VERSION: &str = "6.16.6";
+            }
+            pub mod grafana {
+                pub const NAME: &str = "grafana";
+                pub const OPERATOR_CHART_VERSION: &str = "4.9.2";
+                pub const PORT: u16 = 3000;
+            }
             pub mod dashboard {
                 pub const PATH: &str = "/monitoring";
                 pub const NAME: &str = "dashboard";
@@ -41,18 +177,93 @@ pub mod consts {
 }
 
 pub trait Package {
-    fn resources(&self, config: &ClusterConfig) -> Vec<String>;
+    fn name(&self) -> &'static str;
+
+    /// Rendered resources, or the error failing the render of the stage.
+    fn resources(&self, config: &ClusterConfig) -> Result<Vec<String>, Diagnostic>;
+
+    /// Names of the packages that have to be reconciled before this one with
+    /// `config`. Dependencies that are not part of a deployment are assumed
+    /// to be provided by the cluster itself.
+    fn dependencies(&self, _config: &ClusterConfig) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Value of the `app.kubernetes.io/component` label.
+    fn component(&self) -> &'static str {
+        self.name()
+    }
+
+    /// Value of the `app.kubernetes.io/version` label, omitted if `None`.
+    fn version(&self, _config: &ClusterConfig) -> Option<String> {
+        None
+    }
+
+    /// Problems of the config for this package. Errors fail the render of
+    /// the stage.
+    fn diagnostics(&self, _config: &ClusterConfig) -> Vec<Diagnostic> {
+        vec![]
+    }
+
+    /// Name of the block of the package in [`ClusterConfig::params`], `None`
+    /// if it takes no parameters.
+    fn params_block(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Schema of the parameter block of the package, `None` if it takes no
+    /// parameters.
+    fn params_schema(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Condition under which a registered package is part of a deployment.
+#[derive(Clone)]
+pub enum Rule {
+    /// Only for the listed stages.
+    Stages(Vec<Stage>),
+    /// For every stage except the listed ones.
+    ExceptStages(Vec<Stage>),
+    /// Only for stages of the listed environment classes.
+    Classes(Vec<EnvironmentClass>),
+    /// Only if the predicate holds for the cluster config.
+    When(fn(&ClusterConfig) -> bool),
+}
+
+impl Rule {
+    pub fn applies(&self, config: &ClusterConfig) -> bool {
+        match self {
+            Rule::Stages(stages) => stages.contains(&config.stage),
+            Rule::ExceptStages(stages) => !stages.contains(&config.stage),
+            Rule::Classes(classes) => classes.contains(&config.stage.class()),
+            Rule::When(predicate) => predicate(config),
+        }
+    }
+}
+
+/// A package together with the rules deciding where it is deployed.
+/// A registration without rules applies to every stage.
+pub struct Registration {
+    pub package: Box<dyn Package>,
+    pub rules: Vec<Rule>,
+}
+
+impl Registration {
+    pub fn applies(&self, config: &ClusterConfig) -> bool {
+        self.rules.iter().all(|rule| rule.applies(config))
+    }
 }
 
 strc! {
-    #[derive(Clone)]
+    #[derive(Clone, Serialize, Deserialize, JsonSchema)]
     pub struct Image {
-        pub reference: #[derive(Clone)] pub struct ImageRef {
+        pub reference: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct ImageRef {
             pub registry: String,
             pub name: String,
             pub tag: String,
         },
-        pub pull_policy: #[derive(Clone)] pub enum {
+        pub pull_policy: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub enum {
             Always,
             IfNotPresent,
             Never,
@@ -76,7 +287,7 @@ impl Display for PullPolicy {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub enum ServiceType {
     ClusterIP,
     NodePort,
@@ -93,323 +304,1070 @@ impl Display for ServiceType {
     }
 }
 
+/// A WebAssembly module deployed to a cluster as a package, loaded by
+/// `plugin::packages` with the `plugins` feature. Its parameters are its
+/// block in [`ClusterConfig::params`].
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PluginRef {
+    /// Name of the package and of its module, without `.wasm`.
+    #[schemars(regex(pattern = r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$"))]
+    pub name: String,
+    /// Hex encoded SHA-256 of the module, any other module is rejected.
+    pub sha256: String,
+    pub dependencies: Vec<String>,
+}
+
 strc! {
     pub struct Deployment {
-        pub config: #[derive(Clone)] pub struct ClusterConfig {
-            pub stage: #[derive(Clone)] pub enum {
+        pub config: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct ClusterConfig {
+            pub stage: #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)] #[serde(rename_all = "lowercase")] pub enum {
                 Prod,
                 Dev,
                 Test,
                 Local,
                 Minimal,
             },
-            pub manifest: #[derive(Clone)] pub struct {
-                pub apps: #[derive(Clone)] pub struct {
-                    pub frontend: #[derive(Clone)] pub struct {
-                        pub enabled: bool,
-                        pub replicas: u16,
-                        pub image: Image,
-                        pub service_type: ServiceType,
-                    },
-                    pub backend: #[derive(Clone)] pub struct {
+            /// Name of the cluster the stage is deployed to.
+            pub cluster: String,
+            pub manifest: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                pub apps: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                    /// The settings of the app itself are the [`AppParams`] of
+                    /// its package.
+                    pub frontend: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct AppConfig {
                         pub enabled: bool,
-                        pub replicas: u16,
-                        pub image: Image,
-                        pub service_type: ServiceType,
+                        pub delivery: Delivery,
                     },
+                    pub backend: AppConfig,
                 },
-                pub infrastructure: #[derive(Clone)] pub struct {
-                    pub ingress: #[derive(Clone)] pub struct {
+                /// Datastores of the backend.
+                pub data: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                    pub postgres: Datastore,
+                    pub redis: Datastore,
+                },
+                pub infrastructure: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                    pub ingress: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
                         pub enabled: bool,
                         pub domains: Vec<String>,
-                        pub tls: bool,
                     },
-                    pub monitoring: #[derive(Clone)] pub struct {
+                    #[cfg(feature = "monitoring")]
+                    pub monitoring: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
                         pub enabled: bool,
-                        pub sources: #[derive(Clone)] pub struct {
+                        pub sources: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
                             pub tracing: bool,
                             pub logging: bool,
                             pub metrics: bool,
                         },
-                        pub dashboard: #[derive(Clone)] pub struct {
+                        pub tracing: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                            /// Share of root spans sampled by the apps, between 0 and 1.
+                            #[schemars(range(min = 0.0, max = 1.0))]
+                            pub sampling_ratio: f64,
+                        },
+                        pub alerting: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
+                            pub escalation: Escalation,
+                        },
+                        pub dashboard: #[derive(Clone, Serialize, Deserialize, JsonSchema)] pub struct {
                             pub enabled: bool,
-                            pub service_type: ServiceType,
                         },
                     },
                 },
             },
+            /// Applied in order to the rendered resources of the cluster.
+            pub transformers: Vec<Transformer>,
+            /// Packages loaded from WebAssembly modules.
+            pub plugins: Vec<PluginRef>,
+            /// Parameter blocks of the packages taking [`params`] and of the
+            /// plugins, by package name. Fields of a block replace the
+            /// defaults of the package.
+            pub params: BTreeMap<String, Block>,
         },
         pub packages: Vec<Box<dyn Package>>,
     }
 }
 
+#[derive(Debug)]
+pub enum GraphError {
+    Cycle(Vec<&'static str>),
+}
+
+impl Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::Cycle(packages) => {
+                write!(
+                    f,
+                    "dependency cycle between packages: {}",
+                    packages.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+#[derive(Debug)]
+pub enum RenderError {
+    Yaml(resource::InvalidYaml),
+    Mapping(resource::NotAMapping),
+    Transform(transform::TransformError),
+    Collision(naming::Collision),
+    Rule(slo::InvalidRule),
+    Graph(GraphError),
+    /// A package failed to render its resources.
+    Package(Box<Diagnostic>),
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderError::Yaml(err) => err.fmt(f),
+            RenderError::Mapping(err) => err.fmt(f),
+            RenderError::Transform(err) => err.fmt(f),
+            RenderError::Collision(err) => err.fmt(f),
+            RenderError::Rule(err) => err.fmt(f),
+            RenderError::Graph(err) => err.fmt(f),
+            RenderError::Package(diagnostic) => diagnostic.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<resource::InvalidYaml> for RenderError {
+    fn from(err: resource::InvalidYaml) -> Self {
+        RenderError::Yaml(err)
+    }
+}
+
+impl From<resource::NotAMapping> for RenderError {
+    fn from(err: resource::NotAMapping) -> Self {
+        RenderError::Mapping(err)
+    }
+}
+
+impl From<transform::TransformError> for RenderError {
+    fn from(err: transform::TransformError) -> Self {
+        RenderError::Transform(err)
+    }
+}
+
+impl From<naming::Collision> for RenderError {
+    fn from(err: naming::Collision) -> Self {
+        RenderError::Collision(err)
+    }
+}
+
+impl From<GraphError> for RenderError {
+    fn from(err: GraphError) -> Self {
+        RenderError::Graph(err)
+    }
+}
+
+impl From<slo::InvalidRule> for RenderError {
+    fn from(err: slo::InvalidRule) -> Self {
+        RenderError::Rule(err)
+    }
+}
+
 impl Deployment {
-    pub fn resources(&self) -> Vec<String> {
-        self.packages
+    pub fn resources(&self) -> Result<Vec<Resource>, RenderError> {
+        self.render(&self.config)
+    }
+
+    /// Renders the packages of the deployment with another config.
+    pub(crate) fn render(&self, config: &ClusterConfig) -> Result<Vec<Resource>, RenderError> {
+        let mut resources = self
+            .packages
             .iter()
-            .flat_map(|package| package.resources(&self.config))
+            .map(|package| {
+                let sources = package.resources(config).map_err(|diagnostic| {
+                    RenderError::Package(Box::new(Diagnostic {
+                        package: diagnostic.package.or(Some(package.name())),
+                        ..diagnostic
+                    }))
+                })?;
+                sources
+                    .into_iter()
+                    .map(|source| {
+                        let mut resource = Resource::parse(package.name(), &source)?;
+                        metadata::apply(&mut resource, package.as_ref(), config)?;
+                        Ok(resource)
+                    })
+                    .collect::<Result<Vec<_>, RenderError>>()
+            })
+            .collect::<Result<Vec<_>, RenderError>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        transform::apply(&config.transformers, &mut resources)?;
+        naming::check(&resources)?;
+        slo::check(&resources)?;
+        Ok(resources)
+    }
+
+    fn dependencies(&self, package: &dyn Package) -> Vec<&'static str> {
+        package
+            .dependencies(&self.config)
+            .into_iter()
+            .filter(|dependency| self.packages.iter().any(|p| p.name() == *dependency))
             .collect()
     }
+
+    /// Groups the packages into reconciliation layers. Every package only
+    /// depends on packages of earlier layers, packages within a layer keep
+    /// their registration order.
+    pub fn layers(&self) -> Result<Vec<Vec<&dyn Package>>, GraphError> {
+        let mut remaining: Vec<&dyn Package> = self.packages.iter().map(|p| p.as_ref()).collect();
+        let mut done: Vec<&'static str> = vec![];
+        let mut layers = vec![];
+
+        while !remaining.is_empty() {
+            let (layer, rest): (Vec<&dyn Package>, Vec<&dyn Package>) =
+                remaining.into_iter().partition(|package| {
+                    self.dependencies(*package)
+                        .iter()
+                        .all(|dependency| done.contains(dependency))
+                });
+
+            if layer.is_empty() {
+                return Err(GraphError::Cycle(rest.iter().map(|p| p.name()).collect()));
+            }
+
+            done.extend(layer.iter().map(|p| p.name()));
+            layers.push(layer);
+            remaining = rest;
+        }
+
+        Ok(layers)
+    }
+
+    /// Flux Kustomizations reconciling each package from `<path>/<package>`,
+    /// wired up with `dependsOn` according to the package dependencies.
+    #[cfg(feature = "flux-bootstrap")]
+    pub fn kustomizations(&self, path: &str) -> Result<Vec<String>, RenderError> {
+        self.layers()?
+            .into_iter()
+            .flatten()
+            .map(|package| {
+                let dependencies = self.dependencies(package);
+                let res = timpl! {
+                    apiVersion: kustomize.toolkit.fluxcd.io/v1
+                    kind: Kustomization
+                    metadata:
+                      name: { package.name() }
+                      namespace: { consts::flux::NAMESPACE }
+                    spec:
+                      interval: { consts::reconsilation::INTERVAL }
+                      path: { path }/{ package.name() }
+                      prune: true
+                      wait: true
+                      sourceRef:
+                        kind: GitRepository
+                        name: { consts::flux::SOURCE }
+                      {
+                        timpl_if!(!dependencies.is_empty(), {
+                            dependsOn:
+                            {
+                                timpl_map_ln!(dependencies.iter(), dependency, {
+                                    - name: { dependency }
+                                })
+                            }
+                        })
+                      }
+                };
+                let mut resource = Resource::parse(package.name(), &res)?;
+                metadata::label(&mut resource, &metadata::common_labels(&self.config))?;
+                Ok(resource.to_yaml())
+            })
+            .collect()
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Stage::Prod => "prod",
+            Stage::Dev => "dev",
+            Stage::Test => "test",
+            Stage::Local => "local",
+            Stage::Minimal => "minimal",
+        })
+    }
+}
+
+/// Name of a stage that is not configured.
+#[derive(Debug)]
+pub struct UnknownStage(pub String);
+
+impl Display for UnknownStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown stage {}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownStage {}
+
+impl FromStr for Stage {
+    type Err = UnknownStage;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            Stage::Prod,
+            Stage::Dev,
+            Stage::Test,
+            Stage::Local,
+            Stage::Minimal,
+        ]
+        .into_iter()
+        .find(|stage| stage.to_string() == s)
+        .ok_or_else(|| UnknownStage(s.to_string()))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EnvironmentClass {
+    Production,
+    Shared,
+    Local,
 }
 
-impl ToString for Stage {
-    fn to_string(&self) -> String {
+impl Stage {
+    pub fn class(&self) -> EnvironmentClass {
         match self {
-            Stage::Prod => "prod".to_string(),
-            Stage::Dev => "dev".to_string(),
-            Stage::Test => "test".to_string(),
-            Stage::Local => "local".to_string(),
-            Stage::Minimal => "minimal".to_string(),
+            Stage::Prod => EnvironmentClass::Production,
+            Stage::Dev | Stage::Test => EnvironmentClass::Shared,
+            Stage::Local | Stage::Minimal => EnvironmentClass::Local,
         }
     }
 }
 
 #[derive(Clone)]
 pub struct ServiceMapping {
-    namespace: String,
-    name: String,
-    port: u16,
-    path: String,
+    pub namespace: String,
+    pub name: String,
+    pub port: u16,
+    pub path: String,
+    pub routing: catalog::Routing,
+    pub split: Option<delivery::Split>,
 }
 
-pub struct FrontendPackage {}
+fn container_env(env: &[catalog::Env]) -> String {
+    timpl_if!(!env.is_empty(), {
+        env:
+        {
+            timpl_map_ln!(env.iter(), var, {
+                - name: { var.name }
+                  {
+                      match &var.value {
+                          catalog::EnvValue::Value(value) => timpl! {
+                              value: { yaml::quote(value) }
+                          },
+                          catalog::EnvValue::Secret(secret) => timpl! {
+                              valueFrom:
+                                secretKeyRef:
+                                  name: { secret.name }
+                                  key: { secret.key }
+                          },
+                      }
+                  }
+            })
+        }
+    })
+}
 
-impl Package for FrontendPackage {
-    fn resources(&self, config: &ClusterConfig) -> Vec<String> {
-        let mut res = vec![];
+/// Parameters of the package of an app.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AppParams {
+    pub replicas: u16,
+    /// Tag of the image named like the app in [`consts::apps::REGISTRY`].
+    pub tag: String,
+    pub pull_policy: PullPolicy,
+    pub service_type: ServiceType,
+    pub slos: Vec<Slo>,
+}
 
-        res.push(timpl! {
-            apiVersion: apps/v1
-            kind: Deployment
-            metadata:
-              namespace: { consts::apps::NAMESPACE }
-              name: { consts::apps::frontend::NAME }
-            spec:
-              replicas: { config.manifest.apps.frontend.replicas }
-              selector:
-                matchLabels:
-                  app: { consts::apps::frontend::NAME }
-              template:
-                metadata:
-                  labels:
-                    app: { consts::apps::frontend::NAME }
-                spec:
-                  containers:
-                  - name: { consts::apps::frontend::NAME }
-                    image: { config.manifest.apps.frontend.image.reference }
-                    imagePullPolicy: { config.manifest.apps.frontend.image.pull_policy }
-                    ports:
-                    - containerPort: { consts::apps::frontend::PORT }
-        });
+impl Default for AppParams {
+    /// A single replica of the latest image, without objectives.
+    fn default() -> Self {
+        AppParams {
+            replicas: 1,
+            tag: "latest".to_string(),
+            pull_policy: PullPolicy::Always,
+            service_type: ServiceType::ClusterIP,
+            slos: vec![],
+        }
+    }
+}
 
-        res.push(timpl! {
-            apiVersion: v1
-            kind: Service
-            metadata:
-              namespace: { consts::apps::NAMESPACE }
-              name: { consts::apps::frontend::NAME }
-            spec:
-              type: { config.manifest.apps.backend.service_type }
-              selector:
-                app: { consts::apps::frontend::NAME }
-              ports:
-              - port: { consts::apps::frontend::PORT }
-                targetPort: { consts::apps::frontend::PORT }
-        });
+impl AppParams {
+    /// Image of the tracks of `app` that do not bring their own.
+    pub fn image(&self, app: &catalog::App) -> Image {
+        Image {
+            reference: ImageRef {
+                registry: consts::apps::REGISTRY.to_string(),
+                name: app.name.to_string(),
+                tag: self.tag.clone(),
+            },
+            pull_policy: self.pull_policy.clone(),
+        }
+    }
+
+    /// An app without replicas, and objectives that cannot be met.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut res = vec![];
+
+        if self.replicas == 0 {
+            res.push(Diagnostic::warning("enabled app has no replicas").at("replicas"));
+        }
+        res.extend(slo::diagnostics(&self.slos, "slos"));
 
         res
     }
 }
 
-pub struct BackendPackage {}
+fn app_resources(app: &catalog::App, facts: &Facts, params: &AppParams) -> Vec<String> {
+    let mut res = vec![];
+    let Some(delivery) = facts.app(app.name).map(|fact| &fact.delivery) else {
+        return res;
+    };
+    let tracks = delivery.tracks(app.name, &params.image(app), params.replicas);
+    let app_env = (app.env)(facts);
+    let metrics = app.metrics.as_ref().filter(|_| facts.metrics);
 
-impl Package for BackendPackage {
-    fn resources(&self, config: &ClusterConfig) -> Vec<String> {
-        let mut res = vec![];
+    tracks.iter().for_each(|track| {
+        let env = app_env.iter().cloned();
+        #[cfg(feature = "monitoring")]
+        let env = env.chain(monitoring::tracing_env(app.name, track, facts));
+        let env = env.collect::<Vec<_>>();
 
         res.push(timpl! {
             apiVersion: apps/v1
             kind: Deployment
             metadata:
-              namespace: { consts::apps::NAMESPACE }
-              name: { consts::apps::backend::NAME }
+              namespace: { app.namespace }
+              name: { track.name }
             spec:
-              replicas: { config.manifest.apps.backend.replicas }
+              replicas: { track.replicas }
               selector:
                 matchLabels:
-                  app: { consts::apps::backend::NAME }
+                  app: { track.name }
               template:
                 metadata:
                   labels:
-                    app: { consts::apps::backend::NAME }
+                    app: { track.name }
+                    {
+                      timpl_if!(track.track.is_some(), {
+                          track: { track.track.unwrap_or_default() }
+                      })
+                    }
                 spec:
                   containers:
-                  - name: { consts::apps::backend::NAME }
-                    image: { config.manifest.apps.backend.image.reference }
-                    imagePullPolicy: { config.manifest.apps.backend.image.pull_policy }
+                  - name: { app.name }
+                    image: { yaml::quote(&track.image.reference) }
+                    imagePullPolicy: { track.image.pull_policy }
                     ports:
-                    - containerPort: { consts::apps::backend::PORT }
+                    - name: http
+                      containerPort: { app.port }
+                    {
+                        timpl_map_ln!(metrics.iter(), metrics, {
+                            - name: metrics
+                              containerPort: { metrics.port }
+                        })
+                    }
+                    {
+                        container_env(&env)
+                    }
         });
+    });
 
+    let services = Some((app.name.to_string(), delivery.selector(app.name)))
+        .into_iter()
+        .chain(
+            tracks
+                .iter()
+                .filter(|track| track.name != app.name)
+                .map(|track| (track.name.clone(), track.name.clone())),
+        );
+
+    services.for_each(|(name, selector)| {
         res.push(timpl! {
             apiVersion: v1
             kind: Service
             metadata:
-              namespace: { consts::apps::NAMESPACE }
-              name: { consts::apps::backend::NAME }
+              namespace: { app.namespace }
+              name: { name }
             spec:
-              type: { config.manifest.apps.backend.service_type }
+              type: { params.service_type }
               selector:
-                app: { consts::apps::backend::NAME }
+                app: { selector }
               ports:
-              - port: { consts::apps::backend::PORT }
-                targetPort: { consts::apps::backend::PORT }
+              - name: http
+                port: { app.port }
+                targetPort: http
+              {
+                  timpl_map_ln!(metrics.iter(), metrics, {
+                      - name: metrics
+                        port: { metrics.port }
+                        targetPort: metrics
+                  })
+              }
         });
+    });
+
+    if let Some(metrics) = &metrics {
+        let apps = tracks
+            .iter()
+            .map(|track| track.name.as_str())
+            .collect::<Vec<_>>();
+        res.push(monitoring::pod_monitor(
+            app.namespace,
+            app.name,
+            &apps,
+            "metrics",
+            metrics.path,
+        ));
+        #[cfg(feature = "monitoring")]
+        res.extend(slo::rules(app, &params.slos, facts.escalation));
+    }
+
+    res
+}
+
+/// Prometheus, if it scrapes the metrics of the apps.
+fn app_dependencies(facts: &Facts) -> Vec<&'static str> {
+    match facts.metrics {
+        true => vec!["prometheus"],
+        false => vec![],
+    }
+}
+
+/// Tag of the image serving the main Service of `app`.
+fn app_version(app: &catalog::App, facts: &Facts, params: &AppParams) -> Option<String> {
+    let image = params.image(app);
+    let delivery = &facts.app(app.name)?.delivery;
+    Some(delivery.active_image(&image).reference.tag.clone())
+}
 
+pub struct FrontendPackage {}
+
+impl ScopedPackage for FrontendPackage {
+    type Params = AppParams;
+
+    fn name(&self) -> &'static str {
+        "frontend"
+    }
+
+    fn dependencies(&self, facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        app_dependencies(facts)
+    }
+
+    fn component(&self) -> &'static str {
+        "web"
+    }
+
+    fn version(&self, facts: &Facts, params: &Self::Params) -> Option<String> {
+        app_version(&catalog::frontend(), facts, params)
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        app_resources(&catalog::frontend(), facts, params)
+    }
+
+    fn diagnostics(&self, _facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params.diagnostics()
+    }
+}
+
+pub struct BackendPackage {}
+
+impl ScopedPackage for BackendPackage {
+    type Params = AppParams;
+
+    fn name(&self) -> &'static str {
+        "backend"
+    }
+
+    fn dependencies(&self, facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        let mut res = app_dependencies(facts);
+        if facts.data.postgres.in_cluster() {
+            res.push("postgresql");
+        }
+        if facts.data.redis.in_cluster() {
+            res.push("redis");
+        }
         res
     }
+
+    fn component(&self) -> &'static str {
+        "api"
+    }
+
+    fn version(&self, facts: &Facts, params: &Self::Params) -> Option<String> {
+        app_version(&catalog::backend(), facts, params)
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        app_resources(&catalog::backend(), facts, params)
+    }
+
+    fn diagnostics(&self, _facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params.diagnostics()
+    }
+}
+
+pub struct PostgresPackage {}
+
+impl ScopedPackage for PostgresPackage {
+    type Params = data::Postgres;
+
+    fn name(&self) -> &'static str {
+        "postgresql"
+    }
+
+    fn component(&self) -> &'static str {
+        "database"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::data::postgres::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        match facts.data.postgres.in_cluster() {
+            true => data::postgres(params),
+            false => vec![],
+        }
+    }
+}
+
+pub struct RedisPackage {}
+
+impl ScopedPackage for RedisPackage {
+    type Params = data::Redis;
+
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn component(&self) -> &'static str {
+        "cache"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::data::redis::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        match facts.data.redis.in_cluster() {
+            true => data::redis(params),
+            false => vec![],
+        }
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct TempoPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for TempoPackage {
+    type Params = ();
+
+    fn name(&self) -> &'static str {
+        "tempo"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn component(&self) -> &'static str {
+        "tracing"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &()) -> Option<String> {
+        Some(consts::infrastructure::monitoring::tempo::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, _facts: &Facts, _params: &()) -> Vec<String> {
+        monitoring::tempo()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct CollectorPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for CollectorPackage {
+    type Params = ();
+
+    fn name(&self) -> &'static str {
+        "otel-collector"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn dependencies(&self, _facts: &Facts, _params: &()) -> Vec<&'static str> {
+        vec!["tempo"]
+    }
+
+    fn component(&self) -> &'static str {
+        "tracing"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &()) -> Option<String> {
+        Some(consts::infrastructure::monitoring::collector::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, _facts: &Facts, _params: &()) -> Vec<String> {
+        monitoring::collector()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct LokiPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for LokiPackage {
+    type Params = logging::LokiParams;
+
+    fn name(&self) -> &'static str {
+        "loki"
+    }
+
+    fn component(&self) -> &'static str {
+        "logging"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::infrastructure::monitoring::loki::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        logging::loki(facts, params)
+    }
+
+    fn diagnostics(&self, _facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params.diagnostics()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct PromtailPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for PromtailPackage {
+    type Params = logging::PromtailParams;
+
+    fn name(&self) -> &'static str {
+        "promtail"
+    }
+
+    fn dependencies(&self, _facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        vec!["loki"]
+    }
+
+    fn component(&self) -> &'static str {
+        "logging"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::infrastructure::monitoring::promtail::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        logging::promtail(facts, params)
+    }
+
+    fn diagnostics(&self, _facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params.diagnostics()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct PrometheusPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for PrometheusPackage {
+    type Params = monitoring::PrometheusParams;
+
+    fn name(&self) -> &'static str {
+        "prometheus"
+    }
+
+    fn component(&self) -> &'static str {
+        "metrics"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::infrastructure::monitoring::prometheus::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        monitoring::prometheus(facts, params)
+    }
+
+    fn diagnostics(&self, _facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params.diagnostics()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct GrafanaOperatorPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for GrafanaOperatorPackage {
+    type Params = ();
+
+    fn name(&self) -> &'static str {
+        "grafana-operator"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn component(&self) -> &'static str {
+        "dashboard"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &()) -> Option<String> {
+        Some(consts::infrastructure::monitoring::grafana::OPERATOR_CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, _facts: &Facts, _params: &()) -> Vec<String> {
+        grafana::operator()
+    }
+}
+
+#[cfg(feature = "monitoring")]
+pub struct GrafanaPackage {}
+
+#[cfg(feature = "monitoring")]
+impl ScopedPackage for GrafanaPackage {
+    type Params = grafana::GrafanaParams;
+
+    fn name(&self) -> &'static str {
+        "grafana"
+    }
+
+    fn dependencies(&self, _facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        vec!["grafana-operator"]
+    }
+
+    fn component(&self) -> &'static str {
+        "dashboard"
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        grafana::grafana(facts, params)
+    }
 }
 
 pub struct IngressSystemPackage {}
 
-impl Package for IngressSystemPackage {
-    fn resources(&self, _config: &ClusterConfig) -> Vec<String> {
-        let mut res = vec![];
+impl ScopedPackage for IngressSystemPackage {
+    type Params = ingress::IngressParams;
 
-        res.push(timpl! {
-            apiVersion: source.toolkit.fluxcd.io/v1beta1
-            kind: HelmRepository
-            metadata:
-              name: { consts::infrastructure::ingress::NAME }
-              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
-            spec:
-              interval: { consts::reconsilation::INTERVAL }
-              url: "https://helm.nginx.com/stable"
-        });
+    fn name(&self) -> &'static str {
+        "ingress-system"
+    }
 
-        res.push(timpl! {
-            apiVersion: helm.toolkit.fluxcd.io/v2beta1
-            kind: HelmRelease
-            metadata:
-              name: { consts::infrastructure::ingress::NAME }
-              namespace: { consts::infrastructure::ingress::SYSTEM_NAMESPACE }
-            spec:
-              chart:
-                spec:
-                  sourceRef:
-                    kind: HelmRepository
-                    name: { consts::infrastructure::ingress::NAME }
-                  chart: nginx-ingress
-                  version: 0.15.2
-              interval: { consts::reconsilation::INTERVAL }
-              values:
-                controller:
-                  enableCertManager: true
-                  name: { consts::infrastructure::ingress::NAME }
-                  enableLatencyMetrics: true
-                  config:
-                    name: { consts::infrastructure::ingress::NAME }
-                  service:
-                    name: { consts::infrastructure::ingress::NAME }
-                  serviceAccount:
-                    name: { consts::infrastructure::ingress::NAME }
-                  reportIngressStatus:
-                    leaderElectionLockName: { consts::infrastructure::ingress::NAME }-leader-election
-                prometheus:
-                  create: false
-        });
+    fn params_block(&self) -> Option<&'static str> {
+        Some("ingress")
+    }
 
-        res
+    fn dependencies(&self, facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        match facts.metrics {
+            true => vec!["prometheus"],
+            false => vec![],
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        "ingress-controller"
+    }
+
+    fn version(&self, _facts: &Facts, params: &Self::Params) -> Option<String> {
+        params.backend.backend().version().map(str::to_string)
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        params.backend.backend().system(facts)
     }
 }
 
 pub struct IngressConfigPackage {}
 
-impl Package for IngressConfigPackage {
-    fn resources(&self, config: &ClusterConfig) -> Vec<String> {
-        let mut res = vec![];
-        config
-            .manifest
-            .infrastructure
-            .ingress
-            .domains
+impl ScopedPackage for IngressConfigPackage {
+    type Params = ingress::IngressParams;
+
+    fn name(&self) -> &'static str {
+        "ingress-config"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        Some("ingress")
+    }
+
+    fn dependencies(&self, facts: &Facts, params: &Self::Params) -> Vec<&'static str> {
+        let system = !params.backend.backend().system(facts).is_empty();
+        let issuer = cfg!(feature = "cert-manager") && params.tls;
+        let apps = [consts::apps::frontend::NAME, consts::apps::backend::NAME]
+            .into_iter()
+            .filter(|name| facts.apps.iter().any(|app| app.name == *name));
+        system
+            .then_some("ingress-system")
+            .into_iter()
+            .chain(issuer.then_some("cert-issuer"))
+            .chain(apps)
+            .collect()
+    }
+
+    fn component(&self) -> &'static str {
+        "routing"
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        params.backend.backend().routes(facts, params)
+    }
+
+    fn diagnostics(&self, facts: &Facts, params: &Self::Params) -> Vec<Diagnostic> {
+        params
+            .backend
+            .backend()
+            .diagnostics(facts)
+            .into_iter()
+            .map(|diagnostic| diagnostic.at("backend"))
+            .collect()
+    }
+}
+
+#[cfg(feature = "cert-manager")]
+pub struct CertManagerPackage {}
+
+#[cfg(feature = "cert-manager")]
+impl ScopedPackage for CertManagerPackage {
+    type Params = ingress::IngressParams;
+
+    fn name(&self) -> &'static str {
+        "cert-manager"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        Some("ingress")
+    }
+
+    fn dependencies(&self, facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        match facts.metrics {
+            true => vec!["prometheus"],
+            false => vec![],
+        }
+    }
+
+    fn component(&self) -> &'static str {
+        "certificates"
+    }
+
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        Some(consts::infrastructure::certificates::CHART_VERSION.to_string())
+    }
+
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String> {
+        ingress::certificates::cert_manager(facts, params)
+    }
+}
+
+/// Issuer of the certificates, apart from cert-manager so its CRDs are
+/// installed first.
+#[cfg(feature = "cert-manager")]
+pub struct CertIssuerPackage {}
+
+#[cfg(feature = "cert-manager")]
+impl ScopedPackage for CertIssuerPackage {
+    type Params = ingress::IngressParams;
+
+    fn name(&self) -> &'static str {
+        "cert-issuer"
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        Some("ingress")
+    }
+
+    fn dependencies(&self, _facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        vec!["cert-manager"]
+    }
+
+    fn component(&self) -> &'static str {
+        "certificates"
+    }
+
+    fn resources(&self, _facts: &Facts, params: &Self::Params) -> Vec<String> {
+        vec![ingress::certificates::issuer(
+            params.backend.backend().solver(),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{deployment, Stub},
+        GraphError,
+    };
+
+    fn names(deployment: &crate::Deployment) -> Vec<Vec<&'static str>> {
+        deployment
+            .layers()
+            .expect("no cycle")
             .iter()
-            .for_each(|domain| {
-                let name = {
-                    let mut res = domain.split('.').collect::<Vec<&str>>();
-                    res.reverse();
-                    res.join("-")
-                };
+            .map(|layer| layer.iter().map(|package| package.name()).collect())
+            .collect()
+    }
 
-                let mut services: Vec<ServiceMapping> = vec![];
+    #[test]
+    fn layers_follow_dependencies_in_registration_order() {
+        let deployment = deployment(vec![
+            Stub::new("routes", &["frontend", "backend"]),
+            Stub::new("backend", &["database"]),
+            Stub::new("frontend", &[]),
+            Stub::new("database", &[]),
+        ]);
 
-                if config.manifest.apps.frontend.enabled {
-                    services.push(ServiceMapping {
-                        namespace: consts::apps::NAMESPACE.to_string(),
-                        name: consts::apps::frontend::NAME.to_string(),
-                        port: consts::apps::frontend::PORT,
-                        path: consts::apps::frontend::PATH.to_string(),
-                    })
-                }
-
-                if config.manifest.apps.backend.enabled {
-                    services.push(ServiceMapping {
-                        namespace: consts::apps::NAMESPACE.to_string(),
-                        name: consts::apps::backend::NAME.to_string(),
-                        port: consts::apps::backend::PORT,
-                        path: consts::apps::backend::PATH.to_string(),
-                    })
-                }
-
-                if config.manifest.infrastructure.monitoring.enabled
-                    && config.manifest.infrastructure.monitoring.dashboard.enabled
-                {
-                    services.push(ServiceMapping {
-                        namespace: consts::infrastructure::monitoring::NAMESPACE.to_string(),
-                        name: consts::infrastructure::monitoring::dashboard::NAME.to_string(),
-                        port: consts::infrastructure::monitoring::dashboard::PORT,
-                        path: consts::infrastructure::monitoring::dashboard::PATH.to_string(),
-                    })
-                }
+        assert_eq!(
+            names(&deployment),
+            vec![
+                vec!["frontend", "database"],
+                vec!["backend"],
+                vec!["routes"]
+            ]
+        );
+    }
 
-                res.push(timpl! {
-                    apiVersion: k8s.nginx.org/v1
-                    kind: VirtualServer
-                    metadata:
-                      name: { name }
-                      namespace: { consts::infrastructure::ingress::NAMESPACE }
-                    spec:
-                      host: { domain }
-                      tls:
-                        secret: { name }-tls
-                      routes:
-                      {
-                        timpl_map_ln!(services.iter(), service, {
-                            - path: { service.path }
-                              route: { service.namespace }/{ name }-{ service.name }
-                        })
-                      }
-                });
-                services.iter().for_each(|service| {
-                    res.push(timpl! {
-                        apiVersion: k8s.nginx.org/v1
-                        kind: VirtualServerRoute
-                        metadata:
-                          name: { name }-{ service.name }
-                          namespace: { service.namespace }
-                        spec:
-                          host: { domain }
-                          upstreams:
-                          - name: { service.name }
-                            service: { service.name }
-                            port: { service.port }
-                          subroutes:
-                          - path: { service.path }
-                            action:
-                                pass: { service.name }
-                    });
-                });
-            });
-        res
+    #[test]
+    fn missing_dependencies_are_provided_by_the_cluster() {
+        let deployment = deployment(vec![
+            Stub::new("backend", &["database"]),
+            Stub::new("frontend", &["backend"]),
+        ]);
+
+        assert_eq!(names(&deployment), vec![vec!["backend"], vec!["frontend"]]);
+    }
+
+    #[test]
+    fn cycles_name_the_packages_involved() {
+        let deployment = deployment(vec![
+            Stub::new("base", &[]),
+            Stub::new("a", &["b"]),
+            Stub::new("b", &["a"]),
+            Stub::new("c", &["a", "base"]),
+        ]);
+
+        let Err(GraphError::Cycle(packages)) = deployment.layers() else {
+            panic!("cycle not detected");
+        };
+        assert_eq!(packages, vec!["a", "b", "c"]);
     }
 }
@@ -0,0 +1,119 @@
+//! Log collection with Promtail and storage in Loki.
+
+use std::fmt::Display;
+
+use crate::diagnostics::Diagnostic;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "monitoring")]
+mod stack;
+#[cfg(feature = "monitoring")]
+pub use stack::*;
+
+/// Where Loki keeps chunks and index.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum LogStorage {
+    /// Volume of a single Loki instance.
+    Filesystem { size: String },
+    /// S3 compatible object storage. The secret provides `AWS_ACCESS_KEY_ID`
+    /// and `AWS_SECRET_ACCESS_KEY` and has to exist in the monitoring
+    /// namespace.
+    S3 {
+        endpoint: String,
+        region: String,
+        bucket: String,
+        secret: String,
+    },
+}
+
+/// Drops log lines of a namespace below `level`.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LevelFilter {
+    pub namespace: String,
+    pub level: Level,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        })
+    }
+}
+
+/// Parameters of the Loki package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LokiParams {
+    pub retention_days: u16,
+    pub storage: LogStorage,
+}
+
+impl Default for LokiParams {
+    fn default() -> Self {
+        LokiParams {
+            retention_days: 7,
+            storage: LogStorage::Filesystem {
+                size: "10Gi".to_string(),
+            },
+        }
+    }
+}
+
+impl LokiParams {
+    /// A retention that keeps nothing.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self.retention_days {
+            0 => vec![Diagnostic::error("retention of 0 days keeps no logs").at("retention_days")],
+            _ => vec![],
+        }
+    }
+}
+
+/// Parameters of the Promtail package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PromtailParams {
+    pub filters: Vec<LevelFilter>,
+}
+
+impl Default for PromtailParams {
+    fn default() -> Self {
+        PromtailParams {
+            filters: vec![LevelFilter {
+                namespace: "kube-system".to_string(),
+                level: Level::Warn,
+            }],
+        }
+    }
+}
+
+impl PromtailParams {
+    /// Level filters that drop nothing.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.filters
+            .iter()
+            .enumerate()
+            .filter(|(_, filter)| filter.level == Level::Trace)
+            .map(|(i, filter)| {
+                Diagnostic::warning(format!(
+                    "filter of namespace {} drops nothing below trace",
+                    filter.namespace
+                ))
+                .at(format!("filters[{i}].level"))
+            })
+            .collect()
+    }
+}
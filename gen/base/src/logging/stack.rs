@@ -0,0 +1,254 @@
+//! Helm releases of Loki and Promtail.
+
+use timpl::*;
+
+use super::{Level, LevelFilter, LogStorage, LokiParams, PromtailParams};
+use crate::{
+    consts, monitoring,
+    params::{App, Facts},
+    yaml, EnvironmentClass,
+};
+
+impl LevelFilter {
+    /// Regular expression matching the levels that are dropped.
+    fn dropped(&self) -> String {
+        let levels = [Level::Trace, Level::Debug, Level::Info, Level::Warn]
+            .into_iter()
+            .filter(|level| *level < self.level)
+            .map(|level| match level {
+                Level::Warn => "warn(ing)?".to_string(),
+                level => level.to_string(),
+            })
+            .collect::<Vec<_>>();
+        format!("(?i)^({})$", levels.join("|"))
+    }
+}
+
+/// Single process Loki sized for workstations.
+fn small(facts: &Facts) -> bool {
+    facts.stage.class() == EnvironmentClass::Local
+}
+
+/// Loki runs as single binary unless it can share object storage between
+/// separate read, write and backend targets.
+fn single_binary(facts: &Facts, params: &LokiParams) -> bool {
+    small(facts) || matches!(params.storage, LogStorage::Filesystem { .. })
+}
+
+/// Endpoint of the Loki gateway, used for pushing and querying.
+pub fn url() -> String {
+    use consts::infrastructure::monitoring::{loki, NAMESPACE};
+    format!("http://{}.{NAMESPACE}.svc", loki::GATEWAY)
+}
+
+fn storage(storage: &LogStorage) -> String {
+    match storage {
+        LogStorage::Filesystem { .. } => timpl! {
+            type: filesystem
+        },
+        LogStorage::S3 {
+            endpoint,
+            region,
+            bucket,
+            ..
+        } => timpl! {
+            type: s3
+            bucketNames:
+              chunks: { bucket }
+              ruler: { bucket }
+              admin: { bucket }
+            s3:
+              endpoint: { endpoint }
+              region: { region }
+        },
+    }
+}
+
+pub fn loki(facts: &Facts, params: &LokiParams) -> Vec<String> {
+    use consts::infrastructure::monitoring::{loki, NAMESPACE};
+
+    let single = single_binary(facts, params);
+    let small = small(facts);
+    let (object_store, volume) = match &params.storage {
+        LogStorage::Filesystem { size } => ("filesystem", Some(size)),
+        LogStorage::S3 { .. } => ("s3", None),
+    };
+    let (mode, single_replicas, replicas) = if single {
+        ("SingleBinary", 1, 0)
+    } else {
+        ("SimpleScalable", 0, 3)
+    };
+    let mut res = vec![];
+
+    res.push(monitoring::repository(
+        loki::NAME,
+        "https://grafana.github.io/helm-charts",
+    ));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { loki::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { loki::NAME }
+              chart: loki
+              version: { loki::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { loki::NAME }
+            deploymentMode: { mode }
+            loki:
+              auth_enabled: false
+              {
+                  timpl_if!(single, {
+                      commonConfig:
+                        replication_factor: 1
+                  })
+              }
+              schemaConfig:
+                configs:
+                - from: "2024-04-01"
+                  store: tsdb
+                  object_store: { object_store }
+                  schema: v13
+                  index:
+                    prefix: index_
+                    period: 24h
+              storage:
+                {
+                    storage(&params.storage)
+                }
+              limits_config:
+                retention_period: { params.retention_days as u32 * 24 }h
+              compactor:
+                retention_enabled: true
+                delete_request_store: { object_store }
+            {
+                match &params.storage {
+                    LogStorage::S3 { secret, .. } => timpl! {
+                        global:
+                          extraEnvFrom:
+                          - secretRef:
+                              name: { secret }
+                    },
+                    LogStorage::Filesystem { .. } => String::new(),
+                }
+            }
+            singleBinary:
+              replicas: { single_replicas }
+              persistence:
+                enabled: { volume.is_some() }
+                {
+                    timpl_map_ln!(volume.iter(), size, {
+                        size: { yaml::quote(size) }
+                    })
+                }
+              {
+                  timpl_if!(small, {
+                      resources:
+                        requests:
+                          cpu: 100m
+                          memory: 256Mi
+                        limits:
+                          memory: 512Mi
+                  })
+              }
+            read:
+              replicas: { replicas }
+            write:
+              replicas: { replicas }
+            backend:
+              replicas: { replicas }
+            chunksCache:
+              enabled: { !single }
+            resultsCache:
+              enabled: { !single }
+            lokiCanary:
+              enabled: { !small }
+            test:
+              enabled: false
+    });
+
+    res
+}
+
+/// Pipeline stages parsing the JSON logs of an app into a `level` label.
+fn json_stages(app: &App) -> String {
+    timpl! {
+        - match:
+            selector: { yaml::quote(format!("{{{}}}", app.selector())) }
+            stages:
+            - json:
+                expressions:
+                  level: level
+                  trace_id: trace_id
+            - labels:
+                level:
+            - structured_metadata:
+                trace_id:
+    }
+}
+
+fn filter_stages(filter: &LevelFilter) -> String {
+    timpl! {
+        - match:
+            selector: { yaml::quote(format!(r#"{{namespace="{}"}}"#, filter.namespace)) }
+            stages:
+            - json:
+                expressions:
+                  level: level
+            - drop:
+                source: level
+                expression: { yaml::quote(filter.dropped()) }
+    }
+}
+
+pub fn promtail(facts: &Facts, params: &PromtailParams) -> Vec<String> {
+    use consts::infrastructure::monitoring::{loki, promtail, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(monitoring::repository(
+        promtail::NAME,
+        "https://grafana.github.io/helm-charts",
+    ));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { promtail::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { promtail::NAME }
+              chart: promtail
+              version: { promtail::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { promtail::NAME }
+            config:
+              clients:
+              - url: { url() }{ loki::PUSH_PATH }
+              snippets:
+                pipelineStages:
+                - cri: { "{}" }
+                {
+                    params.filters.iter().map(filter_stages).collect::<Vec<_>>().join("\n")
+                }
+                {
+                    facts.apps.iter().map(json_stages).collect::<Vec<_>>().join("\n")
+                }
+    });
+
+    res
+}
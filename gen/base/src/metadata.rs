@@ -0,0 +1,107 @@
+use serde_yaml::{Mapping, Value};
+
+use crate::{consts, naming, ClusterConfig, Package, RenderError, Resource};
+
+/// Kinds whose pod template is labeled as well.
+const WORKLOADS: [&str; 5] = [
+    "Deployment",
+    "StatefulSet",
+    "DaemonSet",
+    "Job",
+    "ReplicaSet",
+];
+
+/// Labels shared by every generated resource of a cluster.
+pub fn common_labels(config: &ClusterConfig) -> Vec<(String, String)> {
+    vec![
+        (
+            "app.kubernetes.io/part-of".to_string(),
+            consts::metadata::PART_OF.to_string(),
+        ),
+        (
+            "app.kubernetes.io/managed-by".to_string(),
+            consts::metadata::MANAGED_BY.to_string(),
+        ),
+        (
+            format!("{}/stage", consts::metadata::DOMAIN),
+            config.stage.to_string(),
+        ),
+        (
+            format!("{}/cluster", consts::metadata::DOMAIN),
+            config.cluster.clone(),
+        ),
+    ]
+}
+
+/// Labels identifying the package a resource belongs to.
+pub fn package_labels(package: &dyn Package, config: &ClusterConfig) -> Vec<(String, String)> {
+    let mut labels = vec![
+        (
+            "app.kubernetes.io/name".to_string(),
+            package.name().to_string(),
+        ),
+        (
+            "app.kubernetes.io/instance".to_string(),
+            naming::child(package.name(), &config.stage.to_string()),
+        ),
+    ];
+    if let Some(version) = package.version(config) {
+        labels.push(("app.kubernetes.io/version".to_string(), version));
+    }
+    labels.push((
+        "app.kubernetes.io/component".to_string(),
+        package.component().to_string(),
+    ));
+    labels.extend(common_labels(config));
+    labels
+}
+
+/// Adds the labels to the resource and, for workloads, to its pod template.
+/// Labels already set by the template are kept and selectors are never
+/// touched, so adding labels cannot change an immutable selector.
+pub fn label(resource: &mut Resource, labels: &[(String, String)]) -> Result<(), RenderError> {
+    insert(resource.mapping_mut(&["metadata", "labels"])?, labels);
+
+    if WORKLOADS.contains(&resource.kind()) {
+        insert(
+            resource.mapping_mut(&["spec", "template", "metadata", "labels"])?,
+            labels,
+        );
+    }
+    Ok(())
+}
+
+pub fn annotate(
+    resource: &mut Resource,
+    annotations: &[(String, String)],
+) -> Result<(), RenderError> {
+    insert(
+        resource.mapping_mut(&["metadata", "annotations"])?,
+        annotations,
+    );
+    Ok(())
+}
+
+/// Applies the standard labels and the package annotation of a package resource.
+pub fn apply(
+    resource: &mut Resource,
+    package: &dyn Package,
+    config: &ClusterConfig,
+) -> Result<(), RenderError> {
+    label(resource, &package_labels(package, config))?;
+    annotate(
+        resource,
+        &[(
+            format!("{}/package", consts::metadata::DOMAIN),
+            package.name().to_string(),
+        )],
+    )
+}
+
+fn insert(mapping: &mut Mapping, entries: &[(String, String)]) {
+    entries.iter().for_each(|(key, value)| {
+        mapping
+            .entry(Value::from(key.as_str()))
+            .or_insert_with(|| Value::from(value.as_str()));
+    });
+}
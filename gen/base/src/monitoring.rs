@@ -0,0 +1,94 @@
+//! Observability wiring of the rendered resources. Monitors are custom
+//! resources of the Prometheus operator.
+
+use timpl::*;
+
+use crate::{ClusterConfig, Sources};
+
+#[cfg(feature = "monitoring")]
+mod stack;
+#[cfg(feature = "monitoring")]
+pub use stack::*;
+
+/// Whether scrape targets are declared in the cluster.
+pub fn metrics(config: &ClusterConfig) -> bool {
+    sources(config).is_some_and(|sources| sources.metrics)
+}
+
+/// Whether container logs are collected.
+pub fn logging(config: &ClusterConfig) -> bool {
+    sources(config).is_some_and(|sources| sources.logging)
+}
+
+/// Whether apps export traces to the collector.
+pub fn tracing(config: &ClusterConfig) -> bool {
+    sources(config).is_some_and(|sources| sources.tracing)
+}
+
+/// Sources of the monitoring stack, `None` if it is disabled.
+#[cfg(feature = "monitoring")]
+fn sources(config: &ClusterConfig) -> Option<&Sources> {
+    let monitoring = &config.manifest.infrastructure.monitoring;
+    monitoring.enabled.then_some(&monitoring.sources)
+}
+
+/// Without the `monitoring` feature there is no stack to collect anything.
+#[cfg(not(feature = "monitoring"))]
+fn sources(_config: &ClusterConfig) -> Option<&Sources> {
+    None
+}
+
+/// Scrapes the named `port` of all Services matching `labels`.
+pub fn service_monitor(
+    namespace: &str,
+    name: &str,
+    labels: &[(&str, &str)],
+    port: &str,
+    path: &str,
+) -> String {
+    timpl! {
+        apiVersion: monitoring.coreos.com/v1
+        kind: ServiceMonitor
+        metadata:
+          name: { name }
+          namespace: { namespace }
+        spec:
+          selector:
+            matchLabels:
+              {
+                  timpl_map_ln!(labels.iter(), label, {
+                      { label.0 }: { label.1 }
+                  })
+              }
+          endpoints:
+          - port: { port }
+            path: { path }
+    }
+}
+
+/// Scrapes the named `port` of all pods whose `app` label is in `apps`.
+/// Unlike a ServiceMonitor, every pod is scraped once even if several
+/// Services select it.
+pub fn pod_monitor(namespace: &str, name: &str, apps: &[&str], port: &str, path: &str) -> String {
+    timpl! {
+        apiVersion: monitoring.coreos.com/v1
+        kind: PodMonitor
+        metadata:
+          name: { name }
+          namespace: { namespace }
+        spec:
+          selector:
+            matchExpressions:
+            - key: app
+              operator: In
+              values:
+              {
+                  timpl_map_ln!(apps.iter(), app, {
+                      - { app }
+                  })
+              }
+          podMetricsEndpoints:
+          - port: { port }
+            path: { path }
+    }
+}
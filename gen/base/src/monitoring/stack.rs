@@ -0,0 +1,273 @@
+//! Helm releases of the monitoring stack.
+
+use timpl::*;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    catalog::Env, consts, delivery::Track, diagnostics::Diagnostic, params::Facts, slo::Escalation,
+    yaml, ClusterConfig,
+};
+
+/// Share of root spans sampled by the apps, `None` if they export no
+/// traces.
+pub fn sampling_ratio(config: &ClusterConfig) -> Option<f64> {
+    let tracing = &config.manifest.infrastructure.monitoring.tracing;
+    super::tracing(config).then_some(tracing.sampling_ratio)
+}
+
+/// OTLP exporter configuration of a track of the app `name`, empty if
+/// tracing is disabled.
+pub fn tracing_env(name: &str, track: &Track, facts: &Facts) -> Vec<Env> {
+    let Some(sampling_ratio) = facts.sampling_ratio else {
+        return vec![];
+    };
+
+    let attributes = [
+        ("deployment.environment", facts.stage.to_string()),
+        ("service.namespace", consts::metadata::PART_OF.to_string()),
+        ("service.version", track.image.reference.tag.clone()),
+    ]
+    .into_iter()
+    .chain(track.track.map(|track| ("app.track", track.to_string())))
+    .map(|(key, value)| format!("{key}={value}"))
+    .collect::<Vec<_>>();
+
+    vec![
+        Env::value("OTEL_SERVICE_NAME", name),
+        Env::value(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            format!(
+                "http://{}.{}.svc:{}",
+                consts::infrastructure::monitoring::collector::NAME,
+                consts::infrastructure::monitoring::NAMESPACE,
+                consts::infrastructure::monitoring::collector::HTTP_PORT,
+            ),
+        ),
+        Env::value("OTEL_EXPORTER_OTLP_PROTOCOL", "http/protobuf"),
+        Env::value("OTEL_RESOURCE_ATTRIBUTES", attributes.join(",")),
+        Env::value("OTEL_TRACES_SAMPLER", "parentbased_traceidratio"),
+        Env::value("OTEL_TRACES_SAMPLER_ARG", sampling_ratio),
+    ]
+}
+
+pub(crate) fn repository(name: &str, url: &str) -> String {
+    timpl! {
+        apiVersion: source.toolkit.fluxcd.io/v1beta1
+        kind: HelmRepository
+        metadata:
+          name: { name }
+          namespace: { consts::infrastructure::monitoring::NAMESPACE }
+        spec:
+          interval: { consts::reconsilation::INTERVAL }
+          url: { url }
+    }
+}
+
+/// Parameters of the Prometheus package.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PrometheusParams {
+    /// Days the metrics are kept.
+    pub retention_days: u16,
+}
+
+impl Default for PrometheusParams {
+    /// The retention of the chart.
+    fn default() -> Self {
+        PrometheusParams { retention_days: 10 }
+    }
+}
+
+impl PrometheusParams {
+    /// A retention that keeps nothing.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self.retention_days {
+            0 => {
+                vec![Diagnostic::error("retention of 0 days keeps no metrics").at("retention_days")]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Prometheus operator and server, picking up the monitors of all
+/// namespaces.
+pub fn prometheus(facts: &Facts, params: &PrometheusParams) -> Vec<String> {
+    use consts::infrastructure::monitoring::{alertmanager, prometheus, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(repository(prometheus::NAME, consts::data::REPOSITORY));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { prometheus::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { prometheus::NAME }
+              chart: kube-prometheus
+              version: { prometheus::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { prometheus::NAME }
+            prometheus:
+              retention: { params.retention_days }d
+              serviceMonitorSelector: { "{}" }
+              serviceMonitorNamespaceSelector: { "{}" }
+              podMonitorSelector: { "{}" }
+              podMonitorNamespaceSelector: { "{}" }
+              ruleSelector: { "{}" }
+              ruleNamespaceSelector: { "{}" }
+            alertmanager:
+              secrets:
+              - { alertmanager::CHAT_SECRET }
+              {
+                  timpl_if!(facts.escalation == Escalation::Page, {
+                      - { alertmanager::PAGER_SECRET }
+                  })
+              }
+              config:
+                {
+                    alertmanager(facts)
+                }
+    });
+
+    res
+}
+
+/// How the alerts of the stage reach people.
+pub fn escalation(config: &ClusterConfig) -> Escalation {
+    config
+        .manifest
+        .infrastructure
+        .monitoring
+        .alerting
+        .escalation
+}
+
+/// Alertmanager routing of a stage. Alerts go to the chat channel of the
+/// stage, critical ones page the on-call instead if the stage escalates to
+/// paging.
+fn alertmanager(facts: &Facts) -> String {
+    use consts::infrastructure::monitoring::alertmanager::{CHAT_SECRET, PAGER_SECRET, SECRETS};
+
+    let page = facts.escalation == Escalation::Page;
+
+    timpl! {
+        route:
+          receiver: notify
+          group_by:
+          - alertname
+          - app
+          - slo
+          {
+              timpl_if!(page, {
+                  routes:
+                  - receiver: page
+                    matchers:
+                    - { yaml::quote(r#"severity="critical""#) }
+              })
+          }
+        receivers:
+        - name: notify
+          slack_configs:
+          - api_url_file: { SECRETS }/{ CHAT_SECRET }/url
+            channel: { yaml::quote(format!("#alerts-{}", facts.stage)) }
+            send_resolved: true
+        {
+            timpl_if!(page, {
+                - name: page
+                  pagerduty_configs:
+                  - routing_key_file: { SECRETS }/{ PAGER_SECRET }/routing-key
+            })
+        }
+    }
+}
+
+/// OpenTelemetry collector receiving OTLP from the apps and forwarding the
+/// traces to Tempo.
+pub fn collector() -> Vec<String> {
+    use consts::infrastructure::monitoring::{collector, tempo, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(repository(
+        collector::NAME,
+        "https://open-telemetry.github.io/opentelemetry-helm-charts",
+    ));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { collector::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { collector::NAME }
+              chart: opentelemetry-collector
+              version: { collector::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            mode: deployment
+            fullnameOverride: { collector::NAME }
+            image:
+              repository: otel/opentelemetry-collector-k8s
+            config:
+              exporters:
+                otlp:
+                  endpoint: { tempo::NAME }.{ NAMESPACE }.svc:{ tempo::OTLP_PORT }
+                  tls:
+                    insecure: true
+              service:
+                pipelines:
+                  traces:
+                    exporters:
+                    - otlp
+    });
+
+    res
+}
+
+/// Tempo storing the traces of the cluster.
+pub fn tempo() -> Vec<String> {
+    use consts::infrastructure::monitoring::{tempo, NAMESPACE};
+
+    let mut res = vec![];
+
+    res.push(repository(
+        tempo::NAME,
+        "https://grafana.github.io/helm-charts",
+    ));
+
+    res.push(timpl! {
+        apiVersion: helm.toolkit.fluxcd.io/v2beta1
+        kind: HelmRelease
+        metadata:
+          name: { tempo::NAME }
+          namespace: { NAMESPACE }
+        spec:
+          chart:
+            spec:
+              sourceRef:
+                kind: HelmRepository
+                name: { tempo::NAME }
+              chart: tempo
+              version: { tempo::CHART_VERSION }
+          interval: { consts::reconsilation::INTERVAL }
+          values:
+            fullnameOverride: { tempo::NAME }
+    });
+
+    res
+}
@@ -0,0 +1,197 @@
+//! Derivation of DNS-1123 label safe resource names.
+//!
+//! Names are lowercase alphanumerics and `-`, at most 63 characters long. If
+//! deriving a name loses information (invalid characters, ambiguous joins or
+//! truncation) a hash of the input is appended, so distinct inputs keep
+//! distinct names and the same input always gets the same name.
+
+use std::fmt::Display;
+
+use crate::Resource;
+
+pub const MAX_LENGTH: usize = 63;
+
+const HASH_LENGTH: usize = 8;
+
+/// Name for a domain, with its labels reversed, e.g. `com-example-app`.
+pub fn domain(domain: &str) -> String {
+    let labels = domain.split('.').rev().collect::<Vec<_>>();
+    let lossy = labels
+        .iter()
+        .any(|label| label.contains('-') || *label == "*");
+    let labels = labels
+        .into_iter()
+        .map(|label| if label == "*" { "wildcard" } else { label })
+        .collect::<Vec<_>>();
+    derive(&labels.join("-"), domain, lossy)
+}
+
+/// Name of a resource belonging to `parent`, e.g. `com-example-app-frontend`.
+pub fn child(parent: &str, suffix: &str) -> String {
+    let joined = format!("{parent}-{suffix}");
+    derive(&joined, &joined, false)
+}
+
+/// Sanitizes an arbitrary string into a name.
+pub fn name(input: &str) -> String {
+    derive(input, input, false)
+}
+
+/// Whether `name` is a DNS-1123 label, usable as a name as it is.
+pub fn is_label(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_LENGTH
+        && name
+            .bytes()
+            .all(|byte| matches!(byte, b'a'..=b'z' | b'0'..=b'9' | b'-'))
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+fn derive(name: &str, source: &str, lossy: bool) -> String {
+    let mut res = String::with_capacity(name.len());
+    name.chars().for_each(|char| {
+        let char = match char.to_ascii_lowercase() {
+            char @ ('a'..='z' | '0'..='9') => char,
+            _ => '-',
+        };
+        if !(char == '-' && (res.is_empty() || res.ends_with('-'))) {
+            res.push(char);
+        }
+    });
+    let res = res.trim_end_matches('-').to_string();
+
+    if !lossy && res == name && res.len() <= MAX_LENGTH {
+        return res;
+    }
+
+    let hash = format!("{:08x}", fnv1a(source));
+    let keep = MAX_LENGTH - HASH_LENGTH - 1;
+    let res = res.get(..keep).unwrap_or(&res).trim_end_matches('-');
+    if res.is_empty() {
+        hash
+    } else {
+        format!("{res}-{hash}")
+    }
+}
+
+/// 32 bit FNV-1a, stable across platforms and Rust versions.
+pub(crate) fn fnv1a(input: &str) -> u32 {
+    input.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Two resources of a deployment sharing kind, namespace and name.
+#[derive(Debug)]
+pub struct Collision {
+    pub kind: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    pub packages: (&'static str, &'static str),
+}
+
+impl Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}/{} is generated by both {} and {}",
+            self.kind,
+            self.namespace.as_deref().unwrap_or_default(),
+            self.name,
+            self.packages.0,
+            self.packages.1,
+        )
+    }
+}
+
+impl std::error::Error for Collision {}
+
+/// Fails on the first pair of resources with the same identity.
+pub fn check(resources: &[Resource]) -> Result<(), Collision> {
+    resources.iter().enumerate().try_for_each(|(i, resource)| {
+        let collision = resources[..i].iter().find(|other| {
+            other.kind() == resource.kind()
+                && other.namespace() == resource.namespace()
+                && other.name() == resource.name()
+        });
+        match collision {
+            Some(other) => Err(Collision {
+                kind: resource.kind().to_string(),
+                namespace: resource.namespace().map(str::to_string),
+                name: resource.name().to_string(),
+                packages: (other.package, resource.package),
+            }),
+            None => Ok(()),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(package: &'static str, kind: &str, namespace: &str, name: &str) -> Resource {
+        let yaml = format!("kind: {kind}\nmetadata:\n  name: {name}\n  namespace: {namespace}\n");
+        Resource::parse(package, &yaml).expect("valid yaml")
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a(""), 0x811c9dc5);
+        assert_eq!(fnv1a("a"), 0xe40c292c);
+    }
+
+    #[test]
+    fn valid_names_are_kept() {
+        assert_eq!(name("frontend"), "frontend");
+        assert_eq!(
+            child("com-example-app", "frontend"),
+            "com-example-app-frontend"
+        );
+        assert_eq!(domain("app.example.com"), "com-example-app");
+    }
+
+    #[test]
+    fn labels_are_lowercase_alphanumerics_and_inner_dashes() {
+        assert!(is_label("my-plugin2"));
+        assert!(!is_label(""));
+        assert!(!is_label("../plugin"));
+        assert!(!is_label("My-Plugin"));
+        assert!(!is_label("-plugin"));
+        assert!(!is_label("plugin-"));
+        assert!(!is_label(&"a".repeat(MAX_LENGTH + 1)));
+    }
+
+    #[test]
+    fn lossy_names_get_a_stable_suffix() {
+        assert_eq!(name("Frontend_API-v2"), "frontend-api-v2-ed4af4c9");
+        assert_eq!(domain("*.example.com"), "com-example-wildcard-459e0f6a");
+        assert_eq!(domain("my-app.example.com"), "com-example-my-app-0d6b39c6");
+        assert_ne!(domain("my-app.example.com"), domain("app.my.example.com"));
+    }
+
+    #[test]
+    fn long_names_are_truncated_with_a_suffix() {
+        let name = name(&"a".repeat(70));
+        assert_eq!(name.len(), MAX_LENGTH);
+        assert_eq!(name, format!("{}-5904740b", "a".repeat(54)));
+    }
+
+    #[test]
+    fn collisions_name_both_packages() {
+        let resources = [
+            resource("frontend", "Service", "apps", "app"),
+            resource("backend", "Service", "data", "app"),
+            resource("backend", "ConfigMap", "apps", "app"),
+            resource("routes", "Service", "apps", "app"),
+        ];
+        let collision = check(&resources).expect_err("services collide");
+        assert_eq!(collision.packages, ("frontend", "routes"));
+        assert_eq!(
+            collision.to_string(),
+            "Service apps/app is generated by both frontend and routes"
+        );
+        assert!(check(&resources[..3]).is_ok());
+    }
+}
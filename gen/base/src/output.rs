@@ -0,0 +1,260 @@
+//! File layout of a rendered fleet and the writers putting it somewhere.
+//!
+//! Every stage gets a directory with one `<package>/resources.yaml` per
+//! package. With the `flux-bootstrap` feature it also gets a
+//! `kustomization.yaml` pointing Flux at `packages.yaml`, which holds the
+//! Kustomization of every package.
+
+use std::{
+    collections::HashSet,
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::fleet::{RenderedFleet, RenderedStage};
+
+/// A file relative to the stages directory.
+pub struct File {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+/// Start of the header of every generated file.
+const GENERATED: &str = "# Generated by";
+
+/// Ownership manifest listing the generated files, relative to the stages
+/// directory.
+const MANIFEST: &str = ".generated";
+
+impl File {
+    /// YAML file of `documents`, marked as generated from the config of
+    /// `stage`.
+    fn new(path: PathBuf, stage: &RenderedStage, documents: &[String]) -> Self {
+        let mut contents = format!(
+            "{GENERATED} {} {} from config {}, do not edit.\n",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            stage.config_hash,
+        );
+        contents.push_str(documents.join("---\n").trim_end());
+        contents.push('\n');
+        File { path, contents }
+    }
+}
+
+/// Entry point of Flux into a stage and the Kustomizations of its packages.
+#[cfg(feature = "flux-bootstrap")]
+fn bootstrap(root: &Path, stage: &RenderedStage) -> [File; 2] {
+    [
+        File::new(
+            root.join("kustomization.yaml"),
+            stage,
+            &[[
+                "apiVersion: kustomize.config.k8s.io/v1beta1",
+                "kind: Kustomization",
+                "resources:",
+                "- packages.yaml",
+            ]
+            .join("\n")],
+        ),
+        File::new(root.join("packages.yaml"), stage, &stage.kustomizations),
+    ]
+}
+
+pub fn files(fleet: &RenderedFleet) -> Vec<File> {
+    fleet
+        .stages
+        .iter()
+        .flat_map(|stage| {
+            let root = Path::new(&stage.stage.to_string()).to_path_buf();
+
+            let mut files = vec![];
+            #[cfg(feature = "flux-bootstrap")]
+            files.extend(bootstrap(&root, stage));
+            files.extend(stage.packages.iter().map(|package| {
+                let documents = package
+                    .resources
+                    .iter()
+                    .map(|resource| resource.to_yaml())
+                    .collect::<Vec<_>>();
+                File::new(
+                    root.join(package.name).join("resources.yaml"),
+                    stage,
+                    &documents,
+                )
+            }));
+
+            files
+        })
+        .collect()
+}
+
+pub trait Writer {
+    fn write(&mut self, files: &[File]) -> std::io::Result<()>;
+}
+
+/// Writes the files into the directory `root`, keeping files placed there
+/// by hand. The files are written to a staging directory next to `root`,
+/// which then takes its place. Generated files are listed in an ownership
+/// manifest, files that were generated before but are not anymore are
+/// pruned.
+pub struct Filesystem {
+    pub root: PathBuf,
+}
+
+fn remove_dir(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)
+}
+
+/// Paths of all files below `dir`, relative to `base`.
+fn walk(base: &Path, dir: &Path, res: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        entries => entries?,
+    };
+    entries.into_iter().try_for_each(|entry| {
+        let path = entry?.path();
+        match path.is_dir() {
+            true => walk(base, &path, res),
+            false => {
+                res.push(path.strip_prefix(base).unwrap_or(&path).to_path_buf());
+                Ok(())
+            }
+        }
+    })
+}
+
+impl Filesystem {
+    /// Directory next to `root` with `suffix` appended to its name.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let name = self.root.file_name().unwrap_or_default().to_string_lossy();
+        self.root.with_file_name(format!(".{name}.{suffix}"))
+    }
+
+    /// Files generated by the previous write, `None` if there is no
+    /// manifest.
+    fn owned(&self) -> std::io::Result<Option<HashSet<PathBuf>>> {
+        match std::fs::read_to_string(self.root.join(MANIFEST)) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            manifest => Ok(Some(
+                manifest?
+                    .lines()
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(PathBuf::from)
+                    .collect(),
+            )),
+        }
+    }
+}
+
+impl Writer for Filesystem {
+    fn write(&mut self, files: &[File]) -> std::io::Result<()> {
+        let staging = self.sibling("staging");
+        let old = self.sibling("old");
+
+        // A previous write stopped between moving `root` away and moving the
+        // staging directory in.
+        if !self.root.exists() && old.exists() {
+            std::fs::rename(&old, &self.root)?;
+        }
+        remove_dir(&staging)?;
+        remove_dir(&old)?;
+
+        files
+            .iter()
+            .try_for_each(|file| write_file(&staging.join(&file.path), &file.contents))?;
+        let mut manifest = vec![format!(
+            "{GENERATED} {}, lists the files it owns.",
+            env!("CARGO_PKG_NAME")
+        )];
+        manifest.extend(files.iter().map(|file| file.path.display().to_string()));
+        write_file(&staging.join(MANIFEST), &(manifest.join("\n") + "\n"))?;
+
+        // Without a manifest, files at generated paths are taken over.
+        let owned = self.owned()?;
+        let generated = files.iter().map(|file| &file.path).collect::<HashSet<_>>();
+        let mut existing = vec![];
+        walk(&self.root, &self.root, &mut existing)?;
+        existing
+            .into_iter()
+            .filter(|path| path != Path::new(MANIFEST))
+            .try_for_each(|path| {
+                let owned = match &owned {
+                    Some(owned) => owned.contains(&path),
+                    None => generated.contains(&path),
+                };
+                if owned {
+                    return Ok(());
+                }
+                if generated.contains(&path) {
+                    return Err(Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!(
+                            "{} was not generated, refusing to overwrite it",
+                            self.root.join(&path).display()
+                        ),
+                    ));
+                }
+
+                let target = staging.join(&path);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(self.root.join(&path), target).map(|_| ())
+            })?;
+
+        if self.root.exists() {
+            std::fs::rename(&self.root, &old)?;
+        }
+        std::fs::rename(&staging, &self.root)?;
+        remove_dir(&old)
+    }
+}
+
+/// Prints all files as one YAML stream, each introduced by a comment with
+/// its path.
+pub struct Stdout;
+
+impl Writer for Stdout {
+    fn write(&mut self, files: &[File]) -> std::io::Result<()> {
+        let mut out = std::io::stdout().lock();
+        files.iter().enumerate().try_for_each(|(i, file)| {
+            if i > 0 {
+                out.write_all(b"---\n")?;
+            }
+            writeln!(out, "# {}", file.path.display())?;
+            out.write_all(file.contents.as_bytes())
+        })
+    }
+}
+
+/// Writes a tar archive of the files. Timestamps and owners are fixed, so
+/// equal fleets give equal archives.
+pub struct Archive<W: Write> {
+    pub out: W,
+}
+
+impl<W: Write> Writer for Archive<W> {
+    fn write(&mut self, files: &[File]) -> std::io::Result<()> {
+        let mut builder = tar::Builder::new(&mut self.out);
+        files.iter().try_for_each(|file| {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(file.contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(0);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder.append_data(&mut header, &file.path, file.contents.as_bytes())
+        })?;
+        builder.finish()
+    }
+}
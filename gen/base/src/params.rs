@@ -0,0 +1,230 @@
+//! Packages that only see their own typed parameters and the facts every
+//! package shares, instead of the whole [`ClusterConfig`].
+//!
+//! The parameters of a package start from their `Default` and are replaced
+//! field by field by the block of the package in [`ClusterConfig::params`].
+//! Packages that have to agree on their parameters share a block.
+
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+
+#[cfg(feature = "monitoring")]
+use crate::slo::Escalation;
+use crate::{
+    catalog, delivery::Delivery, diagnostics::Diagnostic, monitoring, schema, ClusterConfig, Data,
+    Package, ServiceMapping, Stage,
+};
+
+/// Parameter block of a package in the config.
+pub type Block = serde_json::Value;
+
+/// What a package may know about the cluster besides its own parameters.
+pub struct Facts {
+    pub stage: Stage,
+    /// Domains the cluster is served on.
+    pub domains: Vec<String>,
+    /// Whether scrape targets are declared in the cluster.
+    pub metrics: bool,
+    /// Whether container logs are collected.
+    pub logging: bool,
+    /// Share of root spans sampled by the apps, `None` if they export no
+    /// traces.
+    #[cfg(feature = "monitoring")]
+    pub sampling_ratio: Option<f64>,
+    /// How the alerts of the stage reach people.
+    #[cfg(feature = "monitoring")]
+    pub escalation: Escalation,
+    /// Datastores of the backend and where they come from.
+    pub data: Data,
+    /// Apps enabled in the cluster.
+    pub apps: Vec<App>,
+    /// Services routed on every domain, the apps followed by the monitoring
+    /// dashboard.
+    pub services: Vec<ServiceMapping>,
+}
+
+/// An app enabled in the cluster.
+pub struct App {
+    pub name: String,
+    pub namespace: String,
+    /// Tracks of the app, which its routes split the traffic between.
+    pub delivery: Delivery,
+}
+
+impl App {
+    /// Label matchers of the app containers.
+    pub fn selector(&self) -> String {
+        format!(
+            r#"namespace="{}", container="{}""#,
+            self.namespace, self.name
+        )
+    }
+}
+
+impl Facts {
+    pub fn of(config: &ClusterConfig) -> Self {
+        let apps = catalog::apps(config);
+        #[cfg_attr(not(feature = "monitoring"), allow(unused_mut))]
+        let mut services = apps
+            .iter()
+            .map(|app| app.service(config))
+            .collect::<Vec<_>>();
+
+        #[cfg(feature = "monitoring")]
+        if config.manifest.infrastructure.monitoring.enabled
+            && config.manifest.infrastructure.monitoring.dashboard.enabled
+        {
+            use crate::consts::infrastructure::monitoring::{dashboard, NAMESPACE};
+
+            services.push(ServiceMapping {
+                namespace: NAMESPACE.to_string(),
+                name: dashboard::NAME.to_string(),
+                port: dashboard::PORT,
+                path: dashboard::PATH.to_string(),
+                routing: Default::default(),
+                split: None,
+            })
+        }
+
+        Facts {
+            stage: config.stage,
+            domains: config.manifest.infrastructure.ingress.domains.clone(),
+            metrics: monitoring::metrics(config),
+            logging: monitoring::logging(config),
+            #[cfg(feature = "monitoring")]
+            sampling_ratio: monitoring::sampling_ratio(config),
+            #[cfg(feature = "monitoring")]
+            escalation: monitoring::escalation(config),
+            data: config.manifest.data.clone(),
+            apps: apps
+                .iter()
+                .map(|app| App {
+                    name: app.name.to_string(),
+                    namespace: app.namespace.to_string(),
+                    delivery: (app.config)(&config.manifest.apps).delivery.clone(),
+                })
+                .collect(),
+            services,
+        }
+    }
+
+    /// The enabled app `name`.
+    pub fn app(&self, name: &str) -> Option<&App> {
+        self.apps.iter().find(|app| app.name == name)
+    }
+}
+
+/// A package configured by its own parameter block. Every scoped package is
+/// a [`Package`].
+pub trait ScopedPackage {
+    type Params: Default + Serialize + DeserializeOwned + JsonSchema;
+
+    fn name(&self) -> &'static str;
+    fn resources(&self, facts: &Facts, params: &Self::Params) -> Vec<String>;
+
+    /// See [`Package::dependencies`].
+    fn dependencies(&self, _facts: &Facts, _params: &Self::Params) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// See [`Package::params_block`].
+    fn params_block(&self) -> Option<&'static str> {
+        Some(self.name())
+    }
+
+    /// See [`Package::component`].
+    fn component(&self) -> &'static str {
+        self.name()
+    }
+
+    /// See [`Package::version`].
+    fn version(&self, _facts: &Facts, _params: &Self::Params) -> Option<String> {
+        None
+    }
+
+    /// Problems of the parameters, at paths within the parameter block.
+    fn diagnostics(&self, _facts: &Facts, _params: &Self::Params) -> Vec<Diagnostic> {
+        vec![]
+    }
+}
+
+/// Parameters of `package` in `config`.
+pub fn of<P: ScopedPackage>(
+    package: &P,
+    config: &ClusterConfig,
+) -> Result<P::Params, serde_json::Error> {
+    let mut params = serde_json::to_value(P::Params::default())?;
+    let block = ScopedPackage::params_block(package).and_then(|name| config.params.get(name));
+    if let (Some(params), Some(Block::Object(block))) = (params.as_object_mut(), block) {
+        params.extend(block.clone());
+    }
+    serde_json::from_value(params)
+}
+
+/// Block setting every parameter of `package`, an entry of
+/// [`ClusterConfig::params`].
+pub fn block<P: ScopedPackage>(package: &P, params: &P::Params) -> (String, Block) {
+    let name = ScopedPackage::params_block(package).expect("package takes parameters");
+    let params = serde_json::to_value(params).expect("params are serializable");
+    (name.to_string(), params)
+}
+
+/// Path of the parameter block of `package` in the cluster config.
+fn path<P: ScopedPackage>(package: &P) -> String {
+    let block = ScopedPackage::params_block(package).unwrap_or(ScopedPackage::name(package));
+    format!("params.{block}")
+}
+
+impl<P: ScopedPackage> Package for P {
+    fn name(&self) -> &'static str {
+        ScopedPackage::name(self)
+    }
+
+    /// Fails if the parameter block does not deserialize.
+    fn resources(&self, config: &ClusterConfig) -> Result<Vec<String>, Diagnostic> {
+        let params = of(self, config).map_err(|err| Diagnostic::error(err).at(path(self)))?;
+        Ok(ScopedPackage::resources(self, &Facts::of(config), &params))
+    }
+
+    fn dependencies(&self, config: &ClusterConfig) -> Vec<&'static str> {
+        of(self, config)
+            .map(|params| ScopedPackage::dependencies(self, &Facts::of(config), &params))
+            .unwrap_or_default()
+    }
+
+    fn component(&self) -> &'static str {
+        ScopedPackage::component(self)
+    }
+
+    fn version(&self, config: &ClusterConfig) -> Option<String> {
+        let params = of(self, config).ok()?;
+        ScopedPackage::version(self, &Facts::of(config), &params)
+    }
+
+    /// Empty if the parameter block does not deserialize, rendering the
+    /// resources fails then.
+    fn diagnostics(&self, config: &ClusterConfig) -> Vec<Diagnostic> {
+        let path = path(self);
+        let Ok(params) = of(self, config) else {
+            return vec![];
+        };
+        ScopedPackage::diagnostics(self, &Facts::of(config), &params)
+            .into_iter()
+            .map(|diagnostic| Diagnostic {
+                path: Some(match &diagnostic.path {
+                    Some(field) => format!("{path}.{field}"),
+                    None => path.clone(),
+                }),
+                ..diagnostic
+            })
+            .collect()
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        ScopedPackage::params_block(self)
+    }
+
+    fn params_schema(&self) -> Option<serde_json::Value> {
+        ScopedPackage::params_block(self).map(|_| schema::params(&P::Params::default()))
+    }
+}
@@ -0,0 +1,211 @@
+//! Packages compiled to WebAssembly, so packages can be shipped without
+//! changes to this crate.
+//!
+//! A plugin is the module `<name>.wasm` in the plugin directory, pinned in
+//! the cluster config by its SHA-256. It exports its `memory`,
+//! `alloc(len: i32) -> i32` and `render(ptr: i32, len: i32) -> i64`. `render`
+//! gets `{"config": <cluster config>, "params": <params>}` as JSON, with the
+//! block of the plugin in [`ClusterConfig::params`] as `params`, and returns
+//! `ptr << 32 | len` of a JSON array of YAML documents. Modules cannot import
+//! anything, so they have no access to the filesystem, the network or the
+//! clock, and run with bounded fuel and memory.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{diagnostics::Diagnostic, naming, ClusterConfig, Package, PluginRef};
+
+/// Instructions a plugin may execute per render.
+const FUEL: u64 = 100_000_000;
+
+/// Bytes of memory a plugin may use.
+const MEMORY: usize = 64 << 20;
+
+#[derive(Debug)]
+pub enum PluginError {
+    /// The name is no DNS-1123 label, or another plugin or an in-tree
+    /// package has the same name.
+    Name(&'static str),
+    Read(PathBuf, std::io::Error),
+    Hash {
+        pinned: String,
+        actual: String,
+    },
+    /// Invalid module, trap, or exhausted fuel or memory.
+    Wasm(wasmi::Error),
+    /// The result is out of the bounds of the memory, or not a JSON array
+    /// of strings.
+    Output(String),
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginError::Name(reason) => write!(f, "name {reason}"),
+            PluginError::Read(path, err) => write!(f, "reading {} failed: {err}", path.display()),
+            PluginError::Hash { pinned, actual } => {
+                write!(f, "module has sha256 {actual}, but {pinned} is pinned")
+            }
+            PluginError::Wasm(err) => write!(f, "module failed: {err}"),
+            PluginError::Output(err) => write!(f, "module returned invalid resources: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+impl From<wasmi::Error> for PluginError {
+    fn from(err: wasmi::Error) -> Self {
+        PluginError::Wasm(err)
+    }
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    format!("{:x}", sha2::Sha256::digest(bytes))
+}
+
+/// Names and hashes of the modules in `dir`, for pinning them in a cluster
+/// config.
+pub fn discover(dir: &Path) -> std::io::Result<Vec<(String, String)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        entries => entries?,
+    };
+    let mut res = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| !matches!(path, Ok(path) if path.extension() != Some("wasm".as_ref())))
+        .map(|path| {
+            let path = path?;
+            let name = path.file_stem().unwrap_or_default().to_string_lossy();
+            Ok((name.to_string(), sha256(&std::fs::read(&path)?)))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    res.sort();
+    Ok(res)
+}
+
+/// A plugin as a package. Failures to load or run the module fail the
+/// render of the stage.
+pub struct PluginPackage {
+    name: &'static str,
+    dependencies: Vec<&'static str>,
+    module: Result<wasmi::Module, PluginError>,
+}
+
+/// Packages of the `plugins`, loaded from `dir`. The name of a plugin has to
+/// be a DNS-1123 label, as it names its module, directory and resources, and
+/// may not be one of the `builtin` names of packages and parameter blocks, or
+/// the name of another plugin.
+pub fn packages(dir: &Path, plugins: &[PluginRef], builtin: &[&str]) -> Vec<Box<dyn Package>> {
+    let engine = wasmi::Engine::new(wasmi::Config::default().consume_fuel(true));
+    // Package names are `&'static str` everywhere, plugins are loaded once
+    // and live as long as the process.
+    let leak = |name: &str| -> &'static str { Box::leak(name.into()) };
+
+    plugins
+        .iter()
+        .enumerate()
+        .map(|(i, plugin)| {
+            let taken = builtin.contains(&plugin.name.as_str())
+                || plugins[..i].iter().any(|other| other.name == plugin.name);
+            let bytes = if !naming::is_label(&plugin.name) {
+                Err(PluginError::Name("is no DNS-1123 label"))
+            } else if taken {
+                Err(PluginError::Name("is taken by another package"))
+            } else {
+                let path = dir.join(&plugin.name).with_extension("wasm");
+                std::fs::read(&path).map_err(|err| PluginError::Read(path, err))
+            };
+            let module = bytes.and_then(|bytes| match sha256(&bytes) {
+                actual if actual != plugin.sha256 => Err(PluginError::Hash {
+                    pinned: plugin.sha256.clone(),
+                    actual,
+                }),
+                _ => Ok(wasmi::Module::new(&engine, bytes)?),
+            });
+            Box::new(PluginPackage {
+                name: leak(&plugin.name),
+                dependencies: plugin.dependencies.iter().map(|name| leak(name)).collect(),
+                module,
+            }) as Box<dyn Package>
+        })
+        .collect()
+}
+
+impl PluginPackage {
+    fn render(module: &wasmi::Module, input: &str) -> Result<Vec<String>, PluginError> {
+        let limits = wasmi::StoreLimitsBuilder::new().memory_size(MEMORY).build();
+        let mut store = wasmi::Store::new(module.engine(), limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL)?;
+        let instance =
+            wasmi::Linker::new(module.engine()).instantiate_and_start(&mut store, module)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| wasmi::Error::new("module does not export its memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let render = instance.get_typed_func::<(i32, i32), i64>(&store, "render")?;
+
+        let len = input.len() as i32;
+        let ptr = alloc.call(&mut store, len)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input.as_bytes())
+            .map_err(wasmi::Error::from)?;
+        let result = render.call(&mut store, (ptr, len))?;
+        let (ptr, len) = ((result >> 32) as u32 as usize, result as u32 as usize);
+        // `ptr` and `len` are up to the module, read in place within its memory
+        let output = ptr
+            .checked_add(len)
+            .and_then(|end| memory.data(&store).get(ptr..end))
+            .ok_or_else(|| {
+                PluginError::Output(format!(
+                    "{len} bytes at {ptr} exceed the {} bytes of its memory",
+                    memory.data_size(&store)
+                ))
+            })?;
+
+        serde_json::from_slice(output).map_err(|err| PluginError::Output(err.to_string()))
+    }
+}
+
+impl Package for PluginPackage {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dependencies(&self, _config: &ClusterConfig) -> Vec<&'static str> {
+        self.dependencies.clone()
+    }
+
+    fn params_block(&self) -> Option<&'static str> {
+        Some(self.name)
+    }
+
+    /// Runs the module with the config and the parameter block of the
+    /// plugin.
+    fn resources(&self, config: &ClusterConfig) -> Result<Vec<String>, Diagnostic> {
+        let path = format!("plugins[name={}]", self.name);
+        let module = match &self.module {
+            Ok(module) => module,
+            Err(err @ PluginError::Name(_)) => {
+                return Err(Diagnostic::error(err).at(format!("{path}.name")))
+            }
+            Err(err @ PluginError::Hash { .. }) => {
+                return Err(Diagnostic::error(err).at(format!("{path}.sha256")))
+            }
+            Err(err) => return Err(Diagnostic::error(err).at(path)),
+        };
+        let params = config
+            .params
+            .get(self.name)
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({}));
+        let input = serde_json::json!({ "config": config, "params": params }).to_string();
+
+        PluginPackage::render(module, &input).map_err(|err| Diagnostic::error(err).at(path))
+    }
+}
@@ -0,0 +1,277 @@
+//! Provenance of rendered resources: the package that produced a resource,
+//! the config values feeding its fields and whether the stage or the default
+//! manifest sets them. Config values are found by changing one value at a
+//! time, rendering again and comparing the results, so explaining renders
+//! the deployment once per config value. Choices between unit variants, like
+//! the stage, are not traced. A stage setting a manifest value to its default
+//! is not told apart from not setting it.
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use serde_yaml::{value::TaggedValue, Value};
+
+use crate::{
+    diff::{self, Identity},
+    yaml, ClusterConfig, Deployment, Manifest, RenderError, Resource, Stage,
+};
+
+/// Config values `explain` renders the deployment for at most.
+pub const MAX_VALUES: usize = 5000;
+
+#[derive(Debug)]
+pub enum ExplainError {
+    Render(RenderError),
+    /// The config has more values than `MAX_VALUES`.
+    TooLarge(usize),
+}
+
+impl Display for ExplainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExplainError::Render(err) => write!(f, "{err}"),
+            ExplainError::TooLarge(values) => write!(
+                f,
+                "config has {values} values, explaining renders at most {MAX_VALUES}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExplainError {}
+
+impl From<RenderError> for ExplainError {
+    fn from(err: RenderError) -> Self {
+        ExplainError::Render(err)
+    }
+}
+
+/// Where a config value is set.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Origin {
+    /// `default_manifest`, shared by all stages.
+    Default,
+    /// The cluster function of the stage, e.g. `clusters::prod`.
+    Stage(Stage),
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default_manifest"),
+            Origin::Stage(stage) => write!(f, "clusters::{stage}"),
+        }
+    }
+}
+
+/// A rendered resource with the config paths it depends on.
+pub struct Explained {
+    pub resource: Resource,
+    /// Config values that decide whether, or under which name, the resource
+    /// is rendered.
+    pub depends_on: Vec<String>,
+    /// Config values feeding each field, by field path.
+    pub fields: BTreeMap<String, Vec<String>>,
+    /// Where the config values of `depends_on` and `fields` are set.
+    pub origins: BTreeMap<String, Origin>,
+}
+
+impl Explained {
+    /// YAML of the resource with its package and dependencies as header and
+    /// the config values as comments on the fields they feed.
+    pub fn to_yaml(&self) -> String {
+        let mut res = format!("# package: {}\n", self.resource.package);
+        if !self.depends_on.is_empty() {
+            res.push_str(&format!(
+                "# depends on: {}\n",
+                self.sources(&self.depends_on)
+            ));
+        }
+        res.push_str(&yaml::to_annotated_string(&self.resource.value, &|path| {
+            let paths = self.fields.get(path)?;
+            Some(format!("<- {}", self.sources(paths)))
+        }));
+        res
+    }
+
+    /// Config paths with the origin of their value.
+    fn sources(&self, paths: &[String]) -> String {
+        paths
+            .iter()
+            .map(|path| match self.origins.get(path) {
+                Some(origin) => format!("{path} set in {origin}"),
+                None => path.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Whether one of the paths is within the other.
+fn nested(path: &str, other: &str) -> bool {
+    let within = |path: &str, parent: &str| {
+        path.strip_prefix(parent)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    };
+    within(path, other) || within(other, path)
+}
+
+/// Origin of the value at `path` in `config`, given the manifest paths the
+/// stage sets to other values than the default manifest.
+fn origin(path: &str, overrides: &[String], config: &ClusterConfig) -> Origin {
+    match path.starts_with("manifest") && !overrides.iter().any(|other| nested(path, other)) {
+        true => Origin::Default,
+        false => Origin::Stage(config.stage),
+    }
+}
+
+/// Copies of `value` with a single leaf changed, or a single sequence item
+/// removed, together with the path of the change.
+fn variations(path: &str, value: &Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Mapping(mapping) => mapping
+            .iter()
+            .flat_map(|(key, child)| {
+                variations(&diff::key_path(path, key), child)
+                    .into_iter()
+                    .map(|(path, child)| {
+                        let mut mapping = mapping.clone();
+                        mapping.insert(key.clone(), child);
+                        (path, Value::Mapping(mapping))
+                    })
+            })
+            .collect(),
+        Value::Sequence(sequence) => (0..sequence.len())
+            .flat_map(|i| {
+                let item_path = diff::item_path(path, sequence, i);
+                let mut removed = sequence.clone();
+                removed.remove(i);
+
+                let changed =
+                    variations(&item_path, &sequence[i])
+                        .into_iter()
+                        .map(move |(path, item)| {
+                            let mut sequence = sequence.clone();
+                            sequence[i] = item;
+                            (path, Value::Sequence(sequence))
+                        });
+                std::iter::once((item_path, Value::Sequence(removed))).chain(changed)
+            })
+            .collect(),
+        Value::Tagged(tagged) => variations(path, &tagged.value)
+            .into_iter()
+            .map(|(path, value)| {
+                let tag = tagged.tag.clone();
+                (path, Value::Tagged(Box::new(TaggedValue { tag, value })))
+            })
+            .collect(),
+        Value::Bool(bool) => vec![(path.to_string(), Value::Bool(!bool))],
+        Value::Number(number) => {
+            let changed = match (number.as_u64(), number.as_i64()) {
+                (Some(number), _) => Value::from(number.saturating_add(1)),
+                (None, Some(number)) => Value::from(number.saturating_add(1)),
+                (None, None) => Value::from(number.as_f64().unwrap_or_default() + 1.0),
+            };
+            vec![(path.to_string(), changed)]
+        }
+        Value::String(string) => vec![(path.to_string(), Value::String(format!("{string}x")))],
+        Value::Null => vec![],
+    }
+}
+
+/// Paths of the scalars within `value`.
+fn leaves(path: &str, value: &Value) -> Vec<String> {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => mapping
+            .iter()
+            .flat_map(|(key, child)| leaves(&diff::key_path(path, key), child))
+            .collect(),
+        Value::Sequence(sequence) if !sequence.is_empty() => sequence
+            .iter()
+            .enumerate()
+            .flat_map(|(i, item)| leaves(&diff::item_path(path, sequence, i), item))
+            .collect(),
+        Value::Tagged(tagged) => leaves(path, &tagged.value),
+        _ => vec![path.to_string()],
+    }
+}
+
+/// Renders the deployment and traces its resources back to the config and
+/// to whether `defaults` or the stage set it. Variations of the config that
+/// do not deserialize or render are skipped.
+pub fn explain(
+    deployment: &Deployment,
+    defaults: &Manifest,
+) -> Result<Vec<Explained>, ExplainError> {
+    let config = diff::config_value(&deployment.config);
+    let variations = variations("", &config);
+    if variations.len() > MAX_VALUES {
+        return Err(ExplainError::TooLarge(variations.len()));
+    }
+
+    let overrides = diff::values(
+        &serde_yaml::to_value(defaults).expect("manifest is serializable"),
+        &config["manifest"],
+    )
+    .into_iter()
+    .map(|field| format!("manifest.{}", field.path))
+    .collect::<Vec<_>>();
+
+    let mut explained = deployment
+        .resources()?
+        .into_iter()
+        .map(|resource| Explained {
+            resource,
+            depends_on: vec![],
+            fields: BTreeMap::new(),
+            origins: BTreeMap::new(),
+        })
+        .collect::<Vec<_>>();
+    let identities = explained
+        .iter()
+        .map(|explained| Identity::of(&explained.resource))
+        .collect::<Vec<_>>();
+
+    variations.into_iter().for_each(|(config_path, config)| {
+        let Ok(config) = serde_yaml::from_value::<ClusterConfig>(config) else {
+            return;
+        };
+        let Ok(resources) = deployment.render(&config) else {
+            return;
+        };
+        let resources = resources
+            .iter()
+            .map(|resource| (Identity::of(resource), resource))
+            .collect::<Vec<_>>();
+
+        explained
+            .iter_mut()
+            .zip(&identities)
+            .for_each(|(explained, identity)| {
+                let Some((_, resource)) = resources.iter().find(|(other, _)| other == identity)
+                else {
+                    explained.depends_on.push(config_path.clone());
+                    explained.origins.insert(
+                        config_path.clone(),
+                        origin(&config_path, &overrides, &deployment.config),
+                    );
+                    return;
+                };
+                diff::values(&explained.resource.value, &resource.value)
+                    .into_iter()
+                    .filter_map(|field| Some(leaves(&field.path, &field.before?)))
+                    .flatten()
+                    .for_each(|path| {
+                        let paths = explained.fields.entry(path).or_default();
+                        if !paths.contains(&config_path) {
+                            paths.push(config_path.clone());
+                        }
+                        explained.origins.insert(
+                            config_path.clone(),
+                            origin(&config_path, &overrides, &deployment.config),
+                        );
+                    });
+            });
+    });
+
+    Ok(explained)
+}
@@ -0,0 +1,120 @@
+use std::fmt::Display;
+
+use serde_yaml::{Mapping, Value};
+
+use crate::RenderError;
+
+/// A template of `package` that rendered into invalid YAML.
+#[derive(Debug)]
+pub struct InvalidYaml {
+    pub package: &'static str,
+    pub message: String,
+    pub source: String,
+}
+
+impl Display for InvalidYaml {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "package {} rendered invalid yaml: {}\n{}",
+            self.package, self.message, self.source
+        )
+    }
+}
+
+impl std::error::Error for InvalidYaml {}
+
+/// A resource of `package` with a value other than a mapping at `path`.
+#[derive(Debug)]
+pub struct NotAMapping {
+    pub package: &'static str,
+    pub path: String,
+}
+
+impl Display for NotAMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.path.is_empty() {
+            true => write!(
+                f,
+                "package {} rendered a resource that is not a mapping",
+                self.package
+            ),
+            false => write!(
+                f,
+                "package {} rendered a resource whose {} is not a mapping",
+                self.package, self.path
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NotAMapping {}
+
+/// A rendered Kubernetes resource together with the package that produced it.
+#[derive(Clone)]
+pub struct Resource {
+    pub package: &'static str,
+    pub value: Value,
+}
+
+impl Resource {
+    /// Parses a rendered template. Templates are part of the generator, so a
+    /// template that is not valid YAML is a bug in the package.
+    pub fn parse(package: &'static str, source: &str) -> Result<Self, InvalidYaml> {
+        let value = serde_yaml::from_str(source).map_err(|err| InvalidYaml {
+            package,
+            message: err.to_string(),
+            source: source.to_string(),
+        })?;
+        Ok(Resource { package, value })
+    }
+
+    fn str(&self, path: &[&str]) -> Option<&str> {
+        path.iter()
+            .try_fold(&self.value, |value, key| value.get(key))
+            .and_then(Value::as_str)
+    }
+
+    pub fn api_version(&self) -> &str {
+        self.str(&["apiVersion"]).unwrap_or_default()
+    }
+
+    pub fn kind(&self) -> &str {
+        self.str(&["kind"]).unwrap_or_default()
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.str(&["metadata", "namespace"])
+    }
+
+    pub fn name(&self) -> &str {
+        self.str(&["metadata", "name"]).unwrap_or_default()
+    }
+
+    /// Mapping at `path`, created along the way if it does not exist yet.
+    pub fn mapping_mut(&mut self, path: &[&str]) -> Result<&mut Mapping, RenderError> {
+        let package = self.package;
+        let invalid = |depth: usize| NotAMapping {
+            package,
+            path: path[..depth].join("."),
+        };
+        path.iter()
+            .enumerate()
+            .try_fold(&mut self.value, |value, (depth, key)| {
+                value
+                    .as_mapping_mut()
+                    .map(|mapping| {
+                        mapping
+                            .entry(Value::from(*key))
+                            .or_insert_with(|| Value::Mapping(Mapping::new()))
+                    })
+                    .ok_or_else(|| invalid(depth))
+            })?
+            .as_mapping_mut()
+            .ok_or_else(|| invalid(path.len()).into())
+    }
+
+    pub fn to_yaml(&self) -> String {
+        crate::yaml::to_string(&self.value)
+    }
+}
@@ -0,0 +1,214 @@
+//! JSON Schema of the cluster config, generated from the config types, and
+//! validation of config files against it.
+
+use std::fmt::Display;
+
+use schemars::{
+    generate::{SchemaGenerator, SchemaSettings},
+    JsonSchema,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{diff, ClusterConfig, Manifest, Package};
+
+/// Subschemas are inlined so every field carries its own default.
+fn generator() -> SchemaGenerator {
+    SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+}
+
+/// Schema of [`ClusterConfig`] with `defaults` as default values of the
+/// manifest, and the parameter blocks of `packages`.
+pub fn config(defaults: &Manifest, packages: &[&dyn Package]) -> Value {
+    let mut schema = generator()
+        .into_root_schema_for::<ClusterConfig>()
+        .to_value();
+    let defaults = serde_json::json!({
+        "manifest": serde_json::to_value(defaults).expect("manifest is serializable"),
+    });
+    with_defaults(&mut schema, &defaults);
+    let params = packages
+        .iter()
+        .filter_map(|package| {
+            Some((
+                package.params_block()?.to_string(),
+                package.params_schema()?,
+            ))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    // Blocks of other names belong to plugins, which pass them on as they
+    // are.
+    schema["properties"]["params"] = serde_json::json!({
+        "type": "object",
+        "properties": params,
+        "additionalProperties": { "type": "object" },
+    });
+    closed(&mut schema);
+    schema
+}
+
+/// Schema of a parameter block of type `T`, with `defaults` as default
+/// values. Every field of a block is optional.
+pub(crate) fn params<T: JsonSchema + Serialize>(defaults: &T) -> Value {
+    let mut schema = generator().into_root_schema_for::<T>().to_value();
+    let defaults = serde_json::to_value(defaults).expect("params are serializable");
+    with_defaults(&mut schema, &defaults);
+    if let Value::Object(object) = &mut schema {
+        object.remove("$schema");
+    }
+    schema
+}
+
+/// Rejects unknown fields, which serde would silently ignore.
+fn closed(schema: &mut Value) {
+    match schema {
+        Value::Object(object) => {
+            if object.contains_key("properties") && !object.contains_key("additionalProperties") {
+                object.insert("additionalProperties".to_string(), Value::Bool(false));
+            }
+            object.values_mut().for_each(closed);
+        }
+        Value::Array(array) => array.iter_mut().for_each(closed),
+        _ => {}
+    }
+}
+
+/// Sets the defaults of the properties of `schema`, which makes them
+/// optional. [`load`] fills them in.
+fn with_defaults(schema: &mut Value, defaults: &Value) {
+    let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) else {
+        return;
+    };
+    let mut defaulted = vec![];
+    properties.iter_mut().for_each(|(key, property)| {
+        if let (Some(default), Some(object)) = (defaults.get(key), property.as_object_mut()) {
+            object.insert("default".to_string(), default.clone());
+            with_defaults(property, default);
+            defaulted.push(Value::from(key.as_str()));
+        }
+    });
+    if let Some(required) = schema.get_mut("required").and_then(Value::as_array_mut) {
+        required.retain(|key| !defaulted.contains(key));
+    }
+}
+
+/// Inserts the defaults of `schema` for the properties missing in `value`.
+fn fill(schema: &Value, value: &mut Value) {
+    let (Some(properties), Value::Object(object)) =
+        (schema.get("properties").and_then(Value::as_object), value)
+    else {
+        return;
+    };
+    properties.iter().for_each(|(key, property)| {
+        match (object.get_mut(key), property.get("default")) {
+            (Some(value), _) => fill(property, value),
+            (None, Some(default)) => {
+                object.insert(key.clone(), default.clone());
+            }
+            (None, None) => {}
+        }
+    });
+}
+
+/// A config that does not match the schema.
+#[derive(Debug)]
+pub struct InvalidConfig {
+    pub violations: Vec<Violation>,
+}
+
+#[derive(Debug)]
+pub struct Violation {
+    /// Path of the offending value, empty for the config itself.
+    pub path: String,
+    pub message: String,
+}
+
+impl InvalidConfig {
+    fn new(message: impl Display) -> Self {
+        InvalidConfig {
+            violations: vec![Violation {
+                path: String::new(),
+                message: message.to_string(),
+            }],
+        }
+    }
+}
+
+impl Display for InvalidConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid cluster config:")?;
+        self.violations
+            .iter()
+            .try_for_each(|violation| match violation.path.is_empty() {
+                true => write!(f, "\n  {}", violation.message),
+                false => write!(f, "\n  {}: {}", violation.path, violation.message),
+            })
+    }
+}
+
+impl std::error::Error for InvalidConfig {}
+
+/// Checks a config defined in code against `schema`, which fails if the
+/// schema and the config types drifted apart.
+pub fn check(schema: &Value, config: &ClusterConfig) -> Result<(), InvalidConfig> {
+    validate(
+        schema,
+        &serde_json::to_value(config).expect("cluster config is serializable"),
+    )
+}
+
+/// Turns a JSON pointer into `config` into a path like the ones of
+/// [`diff::Field`].
+fn path(config: &serde_yaml::Value, pointer: &str) -> String {
+    let segments = pointer
+        .split('/')
+        .skip(1)
+        .map(|segment| segment.replace("~1", "/").replace("~0", "~"));
+    let (path, _) = segments.fold((String::new(), Some(config)), |(path, value), segment| {
+        let value = value.map(|value| match value {
+            serde_yaml::Value::Tagged(tagged) => &tagged.value,
+            value => value,
+        });
+        match (value, segment.parse::<usize>()) {
+            (Some(serde_yaml::Value::Sequence(sequence)), Ok(i)) if i < sequence.len() => {
+                (diff::item_path(&path, sequence, i), sequence.get(i))
+            }
+            (value, _) => {
+                let key = serde_yaml::Value::from(segment.as_str());
+                let child = value.and_then(|value| value.get(&key));
+                (diff::key_path(&path, &key), child)
+            }
+        }
+    });
+    path
+}
+
+fn validate(schema: &Value, config: &Value) -> Result<(), InvalidConfig> {
+    let validator = jsonschema::validator_for(schema).expect("generated schema is valid");
+    let yaml = serde_yaml::to_value(config).expect("json is valid yaml");
+    let violations = validator
+        .iter_errors(config)
+        .map(|error| Violation {
+            path: path(&yaml, &error.instance_path().to_string()),
+            message: error.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(InvalidConfig { violations }),
+    }
+}
+
+/// Reads a cluster config from YAML or JSON, rejecting anything the schema
+/// does not allow. Fields left out take the default of the schema.
+pub fn load(schema: &Value, source: &str) -> Result<ClusterConfig, InvalidConfig> {
+    let yaml = serde_yaml::from_str::<serde_yaml::Value>(source).map_err(InvalidConfig::new)?;
+    let mut config = serde_json::to_value(yaml).map_err(InvalidConfig::new)?;
+    fill(schema, &mut config);
+
+    validate(schema, &config)?;
+    serde_json::from_value(config).map_err(InvalidConfig::new)
+}
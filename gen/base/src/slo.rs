@@ -0,0 +1,374 @@
+//! Service level objectives of the apps, rendered into recording rules and
+//! multi-window, multi-burn-rate alerts as described in the Google SRE
+//! workbook.
+
+use std::fmt::Display;
+
+use timpl::*;
+
+use crate::{catalog, diagnostics::Diagnostic, naming, yaml, Resource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Slo {
+    /// Name of the objective, unique per app.
+    pub name: String,
+    pub indicator: Indicator,
+    /// Share of good requests, e.g. `0.999`.
+    pub objective: f64,
+    /// Compliance window in days.
+    pub window: u16,
+}
+
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Indicator {
+    /// Requests answered without a server error.
+    Availability,
+    /// Requests answered within `threshold` seconds. The threshold has to be
+    /// a bucket boundary of the request duration histogram.
+    Latency { threshold: f64 },
+}
+
+/// How alerts of a stage reach people.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Escalation {
+    /// Fast burning error budgets page the on-call through PagerDuty.
+    Page,
+    /// Every alert is only a message in the chat channel of the stage.
+    Notify,
+}
+
+/// Objectives that cannot be met or evaluated, `path` is the one of `slos`.
+pub fn diagnostics(slos: &[Slo], path: &str) -> Vec<Diagnostic> {
+    let mut res = vec![];
+
+    slos.iter().enumerate().for_each(|(i, slo)| {
+        let path = format!("{path}[name={}]", slo.name);
+        if slos[..i].iter().any(|other| other.name == slo.name) {
+            res.push(Diagnostic::error(format!("duplicate objective {}", slo.name)).at(&path));
+        }
+        if !(slo.objective > 0.0 && slo.objective < 1.0) {
+            res.push(
+                Diagnostic::error(format!(
+                    "objective {} is not between 0 and 1",
+                    slo.objective
+                ))
+                .at(format!("{path}.objective")),
+            );
+        }
+        if slo.window == 0 {
+            res.push(Diagnostic::error("compliance window is empty").at(format!("{path}.window")));
+        }
+        if let Indicator::Latency { threshold } = slo.indicator {
+            if threshold <= 0.0 {
+                res.push(
+                    Diagnostic::error(format!("latency threshold {threshold} is not positive"))
+                        .at(format!("{path}.indicator.threshold")),
+                );
+            }
+        }
+    });
+
+    res
+}
+
+/// Windows of the recording rules.
+const WINDOWS: [&str; 7] = ["5m", "30m", "1h", "2h", "6h", "1d", "3d"];
+
+/// Long window, short window, hours of the long window and share of the
+/// error budget in percent spent within it.
+type BurnWindow = (&'static str, &'static str, u32, u32);
+
+const FAST_BURN: [BurnWindow; 2] = [("1h", "5m", 1, 2), ("6h", "30m", 6, 5)];
+const SLOW_BURN: [BurnWindow; 2] = [("1d", "2h", 24, 10), ("3d", "6h", 72, 10)];
+
+fn record(window: &str) -> String {
+    format!("slo:sli_error:ratio_rate{window}")
+}
+
+/// Ratio of bad requests of `app` over `window`.
+fn error_ratio(app: &catalog::App, slo: &Slo, window: &str) -> String {
+    let selector = app.selector();
+    let total =
+        format!("sum(rate(http_server_request_duration_seconds_count{{{selector}}}[{window}]))");
+
+    match slo.indicator {
+        Indicator::Availability => format!(
+            r#"sum(rate(http_server_request_duration_seconds_count{{{selector}, http_response_status_code=~"5.."}}[{window}])) / {total}"#
+        ),
+        Indicator::Latency { threshold } => format!(
+            r#"1 - sum(rate(http_server_request_duration_seconds_bucket{{{selector}, le="{threshold}"}}[{window}])) / {total}"#
+        ),
+    }
+}
+
+/// Alert condition firing if any pair of windows burns faster than allowed.
+fn burn(app: &catalog::App, slo: &Slo, windows: &[BurnWindow]) -> String {
+    let labels = format!(r#"app="{}", slo="{}""#, app.name, slo.name);
+
+    windows
+        .iter()
+        .map(|(long, short, hours, share)| {
+            let factor = (*share * slo.window as u32 * 24) as f64 / (100 * *hours) as f64;
+            let threshold = format!("({factor} * (1 - {}))", slo.objective);
+            format!(
+                "({}{{{labels}}} > {threshold} and {}{{{labels}}} > {threshold})",
+                record(long),
+                record(short),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+fn alert_name(app: &catalog::App, slo: &Slo, speed: &str) -> String {
+    [app.name, &slo.name, speed, "burn"]
+        .iter()
+        .flat_map(|word| word.split(['-', '_']))
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// PrometheusRule with the recording rules and alerts of the `slos` of
+/// `app`, `None` if it has none. Fast burns page if the stage escalates to
+/// paging.
+pub fn rules(app: &catalog::App, slos: &[Slo], escalation: Escalation) -> Option<String> {
+    if slos.is_empty() {
+        return None;
+    }
+
+    let fast = match escalation {
+        Escalation::Page => "critical",
+        Escalation::Notify => "warning",
+    };
+
+    let groups = slos
+        .iter()
+        .map(|slo| {
+            timpl! {
+                - name: { naming::child(app.name, &slo.name) }
+                  rules:
+                  {
+                      timpl_map_ln!(WINDOWS.iter(), window, {
+                          - record: { record(window) }
+                            expr: { yaml::quote(error_ratio(app, slo, window)) }
+                            labels:
+                              app: { app.name }
+                              slo: { slo.name }
+                      })
+                  }
+                  {
+                      timpl_map_ln!([("fast", &FAST_BURN[..], "2m", fast), ("slow", &SLOW_BURN[..], "1h", "warning")].iter(), alert, {
+                          - alert: { alert_name(app, slo, alert.0) }
+                            expr: { yaml::quote(burn(app, slo, alert.1)) }
+                            for: { alert.2 }
+                            labels:
+                              severity: { alert.3 }
+                              app: { app.name }
+                              slo: { slo.name }
+                            annotations:
+                              summary: { yaml::quote(format!("{} is burning its {} error budget", app.name, slo.name)) }
+                              description: { yaml::quote(format!("The {} objective of {} over {} days will be missed at the current error rate.", slo.objective, app.name, slo.window)) }
+                      })
+                  }
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Some(timpl! {
+        apiVersion: monitoring.coreos.com/v1
+        kind: PrometheusRule
+        metadata:
+          name: { naming::child(app.name, "slo") }
+          namespace: { app.namespace }
+        spec:
+          groups:
+          {
+              groups
+          }
+    })
+}
+
+/// A rule of a PrometheusRule whose expression is no valid PromQL.
+#[derive(Debug)]
+pub struct InvalidRule {
+    pub namespace: Option<String>,
+    pub name: String,
+    pub rule: String,
+    pub expr: String,
+    pub message: String,
+}
+
+impl Display for InvalidRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rule {} of PrometheusRule {}/{} has an invalid expression: {}\n  {}",
+            self.rule,
+            self.namespace.as_deref().unwrap_or_default(),
+            self.name,
+            self.message,
+            self.expr,
+        )
+    }
+}
+
+impl std::error::Error for InvalidRule {}
+
+/// Parses the expressions of all PrometheusRules.
+pub fn check(resources: &[Resource]) -> Result<(), InvalidRule> {
+    resources
+        .iter()
+        .filter(|resource| resource.kind() == "PrometheusRule")
+        .try_for_each(|resource| {
+            let groups = resource.value["spec"]["groups"].as_sequence();
+            let mut rules = groups
+                .into_iter()
+                .flatten()
+                .filter_map(|group| group["rules"].as_sequence())
+                .flatten();
+
+            rules.try_for_each(|rule| {
+                let Some(expr) = rule["expr"].as_str() else {
+                    return Ok(());
+                };
+                promql_parser::parser::parse(expr)
+                    .map(|_| ())
+                    .map_err(|message| InvalidRule {
+                        namespace: resource.namespace().map(str::to_string),
+                        name: resource.name().to_string(),
+                        rule: ["alert", "record"]
+                            .iter()
+                            .find_map(|key| rule[*key].as_str())
+                            .unwrap_or_default()
+                            .to_string(),
+                        expr: expr.to_string(),
+                        message,
+                    })
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slo(name: &str, objective: f64, window: u16) -> Slo {
+        Slo {
+            name: name.to_string(),
+            indicator: Indicator::Availability,
+            objective,
+            window,
+        }
+    }
+
+    /// PrometheusRule of the frontend with `slos`.
+    fn rules_of(slos: Vec<Slo>, escalation: Escalation) -> Option<Resource> {
+        rules(&catalog::frontend(), &slos, escalation)
+            .map(|rules| Resource::parse("frontend", &rules).expect("valid yaml"))
+    }
+
+    #[test]
+    fn burn_rates_spend_the_budget_share_within_the_long_window() {
+        let app = catalog::frontend();
+        let slo = slo("availability", 0.999, 30);
+        let labels = r#"{app="frontend", slo="availability"}"#;
+        let pair = |long: &str, short: &str, factor: &str| {
+            let threshold = format!("({factor} * (1 - 0.999))");
+            format!(
+                "(slo:sli_error:ratio_rate{long}{labels} > {threshold} and slo:sli_error:ratio_rate{short}{labels} > {threshold})"
+            )
+        };
+
+        assert_eq!(
+            burn(&app, &slo, &FAST_BURN),
+            format!("{} or {}", pair("1h", "5m", "14.4"), pair("6h", "30m", "6"))
+        );
+        assert_eq!(
+            burn(&app, &slo, &SLOW_BURN),
+            format!("{} or {}", pair("1d", "2h", "3"), pair("3d", "6h", "1"))
+        );
+    }
+
+    #[test]
+    fn factors_scale_with_the_compliance_window() {
+        let burn = burn(
+            &catalog::frontend(),
+            &slo("availability", 0.99, 7),
+            &FAST_BURN,
+        );
+        assert!(burn.contains("(3.36 * (1 - 0.99))"), "{burn}");
+        assert!(burn.contains("(1.4 * (1 - 0.99))"), "{burn}");
+    }
+
+    #[test]
+    fn rules_record_every_window_and_parse() {
+        let rules = rules_of(vec![slo("availability", 0.999, 30)], Escalation::Notify)
+            .expect("frontend has objectives");
+        let records = rules.value["spec"]["groups"][0]["rules"]
+            .as_sequence()
+            .into_iter()
+            .flatten()
+            .filter_map(|rule| rule["record"].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            WINDOWS.map(|window| format!("slo:sli_error:ratio_rate{window}"))
+        );
+        assert!(check(&[rules]).is_ok());
+    }
+
+    #[test]
+    fn fast_burn_pages_only_if_the_stage_escalates() {
+        let severity = |escalation| {
+            let rules = rules_of(vec![slo("availability", 0.999, 30)], escalation)
+                .expect("frontend has objectives");
+            let rules = &rules.value["spec"]["groups"][0]["rules"];
+            let alerts = rules
+                .as_sequence()
+                .into_iter()
+                .flatten()
+                .filter(|rule| rule.get("alert").is_some());
+            alerts
+                .map(|alert| {
+                    alert["labels"]["severity"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(severity(Escalation::Page), ["critical", "warning"]);
+        assert_eq!(severity(Escalation::Notify), ["warning", "warning"]);
+    }
+
+    #[test]
+    fn apps_without_objectives_get_no_rules() {
+        assert!(rules_of(vec![], Escalation::Page).is_none());
+    }
+
+    #[test]
+    fn invalid_objectives_are_reported() {
+        let slos = [slo("a", 0.999, 30), slo("a", 1.0, 0)];
+        let paths = diagnostics(&slos, "slos")
+            .into_iter()
+            .map(|diagnostic| diagnostic.path.unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "slos[name=a]",
+                "slos[name=a].objective",
+                "slos[name=a].window"
+            ]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Fixtures shared by the tests of the modules.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    data::Datastore, delivery::Delivery, diagnostics::Diagnostic, AppConfig, Apps, ClusterConfig,
+    Data, Deployment, Infrastructure, Ingress, Manifest, Package, Stage,
+};
+#[cfg(feature = "monitoring")]
+use crate::{slo::Escalation, Alerting, Dashboard, Monitoring, Sources, Tracing};
+
+fn app() -> AppConfig {
+    AppConfig {
+        enabled: false,
+        delivery: Delivery::Standard,
+    }
+}
+
+/// Config of a test stage without apps, datastores, ingress or monitoring.
+pub fn config() -> ClusterConfig {
+    ClusterConfig {
+        stage: Stage::Test,
+        cluster: "test".to_string(),
+        manifest: Manifest {
+            apps: Apps {
+                frontend: app(),
+                backend: app(),
+            },
+            data: Data {
+                postgres: Datastore::Disabled,
+                redis: Datastore::Disabled,
+            },
+            infrastructure: Infrastructure {
+                ingress: Ingress {
+                    enabled: false,
+                    domains: vec![],
+                },
+                #[cfg(feature = "monitoring")]
+                monitoring: Monitoring {
+                    enabled: false,
+                    sources: Sources {
+                        tracing: false,
+                        logging: false,
+                        metrics: false,
+                    },
+                    tracing: Tracing {
+                        sampling_ratio: 0.0,
+                    },
+                    alerting: Alerting {
+                        escalation: Escalation::Notify,
+                    },
+                    dashboard: Dashboard { enabled: false },
+                },
+            },
+        },
+        transformers: vec![],
+        plugins: vec![],
+        params: BTreeMap::new(),
+    }
+}
+
+/// Package with fixed dependencies and resources.
+pub struct Stub {
+    pub name: &'static str,
+    pub dependencies: Vec<&'static str>,
+    pub resources: Vec<String>,
+}
+
+impl Stub {
+    pub fn new(name: &'static str, dependencies: &[&'static str]) -> Self {
+        Stub {
+            name,
+            dependencies: dependencies.to_vec(),
+            resources: vec![],
+        }
+    }
+}
+
+impl Package for Stub {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn resources(&self, _config: &ClusterConfig) -> Result<Vec<String>, Diagnostic> {
+        Ok(self.resources.clone())
+    }
+
+    fn dependencies(&self, _config: &ClusterConfig) -> Vec<&'static str> {
+        self.dependencies.clone()
+    }
+}
+
+/// Deployment of `packages` with [`config`].
+pub fn deployment(packages: Vec<Stub>) -> Deployment {
+    Deployment {
+        config: config(),
+        packages: packages
+            .into_iter()
+            .map(|package| Box::new(package) as Box<dyn Package>)
+            .collect(),
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt::Display;
+
+use serde_yaml::{Mapping, Value};
+
+use crate::{RenderError, Resource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Selects the resources a transformer applies to. Unset fields match
+/// everything, labels have to match all.
+#[derive(Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Target {
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub name: Option<String>,
+    pub labels: Vec<(String, String)>,
+}
+
+impl Target {
+    pub fn matches(&self, resource: &Resource) -> bool {
+        self.kind
+            .as_deref()
+            .is_none_or(|kind| kind == resource.kind())
+            && self
+                .namespace
+                .as_deref()
+                .is_none_or(|namespace| Some(namespace) == resource.namespace())
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|name| name == resource.name())
+            && self.labels.iter().all(|(key, value)| {
+                resource.value["metadata"]["labels"][key.as_str()].as_str() == Some(value)
+            })
+    }
+}
+
+/// RFC 6902 operation, paths are RFC 6901 JSON pointers and values YAML.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Operation {
+    Add { path: String, value: String },
+    Remove { path: String },
+    Replace { path: String, value: String },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: String },
+}
+
+/// Post-render modification of the resources of a deployment.
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Transformer {
+    /// Strategic merge patch given as YAML. Maps are merged, `null` removes
+    /// a key, lists of objects are merged by their `name` (or port) key and
+    /// `$patch: merge`, `$patch: delete` and `$patch: replace` work like in
+    /// Kubernetes, on maps and on list elements.
+    StrategicMerge {
+        target: Target,
+        patch: String,
+    },
+    Json6902 {
+        target: Target,
+        operations: Vec<Operation>,
+    },
+    NamePrefix {
+        target: Target,
+        prefix: String,
+    },
+    NameSuffix {
+        target: Target,
+        suffix: String,
+    },
+    CommonAnnotations {
+        target: Target,
+        annotations: Vec<(String, String)>,
+    },
+}
+
+#[derive(Debug)]
+pub enum TransformError {
+    InvalidYaml(String),
+    PathNotFound(String),
+    TestFailed(String),
+    /// `$patch` directive that is unknown or cannot be applied where it is
+    /// used.
+    Directive(String),
+}
+
+impl Display for TransformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransformError::InvalidYaml(err) => write!(f, "invalid yaml in patch: {err}"),
+            TransformError::PathNotFound(path) => write!(f, "patch path not found: {path}"),
+            TransformError::TestFailed(path) => write!(f, "patch test failed at: {path}"),
+            TransformError::Directive(directive) => {
+                write!(f, "unsupported `$patch: {directive}` in patch")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransformError {}
+
+impl Transformer {
+    pub fn target(&self) -> &Target {
+        match self {
+            Transformer::StrategicMerge { target, .. }
+            | Transformer::Json6902 { target, .. }
+            | Transformer::NamePrefix { target, .. }
+            | Transformer::NameSuffix { target, .. }
+            | Transformer::CommonAnnotations { target, .. } => target,
+        }
+    }
+
+    pub fn apply(&self, resource: &mut Resource) -> Result<(), RenderError> {
+        if !self.target().matches(resource) {
+            return Ok(());
+        }
+
+        match self {
+            Transformer::StrategicMerge { patch, .. } => {
+                merge(&mut resource.value, parse(patch)?)?;
+            }
+            Transformer::Json6902 { operations, .. } => {
+                operations
+                    .iter()
+                    .try_for_each(|operation| operation.apply(&mut resource.value))?;
+            }
+            Transformer::NamePrefix { prefix, .. } => {
+                let name = format!("{prefix}{}", resource.name());
+                resource
+                    .mapping_mut(&["metadata"])?
+                    .insert("name".into(), name.into());
+            }
+            Transformer::NameSuffix { suffix, .. } => {
+                let name = format!("{}{suffix}", resource.name());
+                resource
+                    .mapping_mut(&["metadata"])?
+                    .insert("name".into(), name.into());
+            }
+            Transformer::CommonAnnotations { annotations, .. } => {
+                let mapping = resource.mapping_mut(&["metadata", "annotations"])?;
+                annotations.iter().for_each(|(key, value)| {
+                    mapping.insert(key.as_str().into(), value.as_str().into());
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Applies the transformers in order to every resource they target.
+pub fn apply(transformers: &[Transformer], resources: &mut [Resource]) -> Result<(), RenderError> {
+    transformers.iter().try_for_each(|transformer| {
+        resources
+            .iter_mut()
+            .try_for_each(|resource| transformer.apply(resource))
+    })
+}
+
+fn parse(yaml: &str) -> Result<Value, TransformError> {
+    serde_yaml::from_str(yaml).map_err(|err| TransformError::InvalidYaml(err.to_string()))
+}
+
+/// Keys identifying list elements, in order of preference. Keys every
+/// element of the list has come first, so a patch naming a port still
+/// merges it by its port.
+const MERGE_KEYS: [&str; 4] = ["name", "containerPort", "port", "mountPath"];
+
+/// `$patch` directive of a map in a strategic merge patch.
+#[derive(Clone, Copy, PartialEq)]
+enum Directive {
+    Merge,
+    Delete,
+    Replace,
+}
+
+/// Removes the directive from a patch value.
+fn directive(patch: &mut Value) -> Result<Option<Directive>, TransformError> {
+    let Some(directive) = patch
+        .as_mapping_mut()
+        .and_then(|patch| patch.remove("$patch"))
+    else {
+        return Ok(None);
+    };
+    match directive.as_str() {
+        Some("merge") => Ok(Some(Directive::Merge)),
+        Some("delete") => Ok(Some(Directive::Delete)),
+        Some("replace") => Ok(Some(Directive::Replace)),
+        _ => Err(TransformError::Directive(
+            serde_yaml::to_string(&directive)
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+        )),
+    }
+}
+
+/// `patch` applied to nothing, which drops its directives.
+fn created(patch: Value) -> Result<Value, TransformError> {
+    let mut value = match patch {
+        Value::Mapping(_) => Value::Mapping(Mapping::new()),
+        patch => return Ok(patch),
+    };
+    merge(&mut value, patch)?;
+    Ok(value)
+}
+
+fn merge(target: &mut Value, mut patch: Value) -> Result<(), TransformError> {
+    match directive(&mut patch)? {
+        Some(Directive::Replace) => {
+            *target = patch;
+            return Ok(());
+        }
+        // Only the map or list containing a map can delete it.
+        Some(Directive::Delete) => return Err(TransformError::Directive("delete".to_string())),
+        Some(Directive::Merge) | None => {}
+    }
+
+    match (target, patch) {
+        (Value::Mapping(target), Value::Mapping(patch)) => {
+            for (key, mut value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                    continue;
+                }
+                match directive(&mut value)? {
+                    Some(Directive::Delete) => {
+                        target.remove(&key);
+                    }
+                    Some(Directive::Replace) => {
+                        target.insert(key, value);
+                    }
+                    Some(Directive::Merge) | None => match target.get_mut(&key) {
+                        Some(existing) => merge(existing, value)?,
+                        None => {
+                            target.insert(key, created(value)?);
+                        }
+                    },
+                }
+            }
+        }
+        (Value::Sequence(target), Value::Sequence(patch)) => {
+            let patch = patch
+                .into_iter()
+                .map(|mut item| Ok((directive(&mut item)?, item)))
+                .collect::<Result<Vec<_>, TransformError>>()?;
+            if patch
+                .iter()
+                .any(|(directive, _)| *directive == Some(Directive::Replace))
+            {
+                *target = patch
+                    .into_iter()
+                    .filter(|(directive, _)| {
+                        !matches!(directive, Some(Directive::Replace | Directive::Delete))
+                    })
+                    .map(|(_, item)| item)
+                    .collect();
+                return Ok(());
+            }
+            let keyed = patch.iter().all(|(directive, item)| {
+                MERGE_KEYS.iter().any(|key| item.get(key).is_some()) || directive.is_some()
+            });
+            if !keyed {
+                *target = patch.into_iter().map(|(_, item)| item).collect();
+                return Ok(());
+            }
+            for (directive, item) in patch {
+                let keys = || MERGE_KEYS.iter().filter(|key| item.get(key).is_some());
+                let key = keys()
+                    .find(|key| target.iter().all(|existing| existing.get(key).is_some()))
+                    .or_else(|| keys().next());
+                let position = key.and_then(|key| {
+                    target
+                        .iter()
+                        .position(|existing| existing.get(key) == item.get(key))
+                });
+                match (position, directive) {
+                    (Some(position), Some(Directive::Delete)) => {
+                        target.remove(position);
+                    }
+                    (None, Some(Directive::Delete)) => {}
+                    (Some(position), _) => merge(&mut target[position], item)?,
+                    (None, _) => target.push(created(item)?),
+                }
+            }
+        }
+        (target, patch) => *target = created(patch)?,
+    }
+    Ok(())
+}
+
+fn tokens(pointer: &str) -> Result<Vec<String>, TransformError> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    pointer
+        .strip_prefix('/')
+        .map(|pointer| {
+            pointer
+                .split('/')
+                .map(|token| token.replace("~1", "/").replace("~0", "~"))
+                .collect()
+        })
+        .ok_or_else(|| TransformError::PathNotFound(pointer.to_string()))
+}
+
+fn index(sequence: &[Value], token: &str, pointer: &str) -> Result<usize, TransformError> {
+    token
+        .parse::<usize>()
+        .ok()
+        .filter(|index| *index < sequence.len())
+        .ok_or_else(|| TransformError::PathNotFound(pointer.to_string()))
+}
+
+fn resolve<'a>(
+    value: &'a mut Value,
+    tokens: &[String],
+    pointer: &str,
+) -> Result<&'a mut Value, TransformError> {
+    tokens.iter().try_fold(value, |value, token| match value {
+        Value::Mapping(mapping) => mapping
+            .get_mut(token.as_str())
+            .ok_or_else(|| TransformError::PathNotFound(pointer.to_string())),
+        Value::Sequence(sequence) => {
+            let index = index(sequence, token, pointer)?;
+            Ok(&mut sequence[index])
+        }
+        _ => Err(TransformError::PathNotFound(pointer.to_string())),
+    })
+}
+
+fn add(value: &mut Value, pointer: &str, item: Value) -> Result<(), TransformError> {
+    let tokens = tokens(pointer)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        *value = item;
+        return Ok(());
+    };
+    match resolve(value, parent, pointer)? {
+        Value::Mapping(mapping) => {
+            mapping.insert(last.as_str().into(), item);
+        }
+        Value::Sequence(sequence) if last == "-" => sequence.push(item),
+        Value::Sequence(sequence) => {
+            let index = last
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index <= sequence.len())
+                .ok_or_else(|| TransformError::PathNotFound(pointer.to_string()))?;
+            sequence.insert(index, item);
+        }
+        _ => return Err(TransformError::PathNotFound(pointer.to_string())),
+    }
+    Ok(())
+}
+
+fn remove(value: &mut Value, pointer: &str) -> Result<Value, TransformError> {
+    let tokens = tokens(pointer)?;
+    let (last, parent) = tokens
+        .split_last()
+        .ok_or_else(|| TransformError::PathNotFound(pointer.to_string()))?;
+    match resolve(value, parent, pointer)? {
+        Value::Mapping(mapping) => mapping
+            .remove(last.as_str())
+            .ok_or_else(|| TransformError::PathNotFound(pointer.to_string())),
+        Value::Sequence(sequence) => {
+            let index = index(sequence, last, pointer)?;
+            Ok(sequence.remove(index))
+        }
+        _ => Err(TransformError::PathNotFound(pointer.to_string())),
+    }
+}
+
+impl Operation {
+    fn apply(&self, value: &mut Value) -> Result<(), TransformError> {
+        match self {
+            Operation::Add { path, value: item } => add(value, path, parse(item)?),
+            Operation::Remove { path } => remove(value, path).map(|_| ()),
+            Operation::Replace { path, value: item } => {
+                *resolve(value, &tokens(path)?, path)? = parse(item)?;
+                Ok(())
+            }
+            Operation::Move { from, path } => {
+                let item = remove(value, from)?;
+                add(value, path, item)
+            }
+            Operation::Copy { from, path } => {
+                let item = resolve(value, &tokens(from)?, from)?.clone();
+                add(value, path, item)
+            }
+            Operation::Test {
+                path,
+                value: expected,
+            } => {
+                if *resolve(value, &tokens(path)?, path)? == parse(expected)? {
+                    Ok(())
+                } else {
+                    Err(TransformError::TestFailed(path.clone()))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(yaml: &str) -> Resource {
+        Resource::parse("app", yaml).expect("valid yaml")
+    }
+
+    fn merged(yaml: &str, patch: &str) -> Result<Value, RenderError> {
+        let mut resource = resource(yaml);
+        Transformer::StrategicMerge {
+            target: Target::default(),
+            patch: patch.to_string(),
+        }
+        .apply(&mut resource)?;
+        Ok(resource.value)
+    }
+
+    fn yaml(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).expect("valid yaml")
+    }
+
+    const DEPLOYMENT: &str = "
+kind: Deployment
+metadata:
+  name: app
+spec:
+  template:
+    spec:
+      containers:
+      - name: app
+        image: app:1
+        ports:
+        - containerPort: 80
+          protocol: TCP
+      - name: sidecar
+        image: sidecar:1
+";
+
+    #[test]
+    fn list_elements_merge_by_their_key() {
+        let patch = "
+spec:
+  template:
+    spec:
+      containers:
+      - name: app
+        image: app:2
+        ports:
+        - containerPort: 80
+          name: http
+        - containerPort: 9090
+      - name: proxy
+        image: proxy:1
+";
+        let value = merged(DEPLOYMENT, patch).expect("valid patch");
+        let expected = yaml(
+            "
+- name: app
+  image: app:2
+  ports:
+  - containerPort: 80
+    protocol: TCP
+    name: http
+  - containerPort: 9090
+- name: sidecar
+  image: sidecar:1
+- name: proxy
+  image: proxy:1
+",
+        );
+        assert_eq!(value["spec"]["template"]["spec"]["containers"], expected);
+    }
+
+    #[test]
+    fn lists_without_keys_are_replaced() {
+        let value = merged("spec:\n  args: [a, b]\n", "spec:\n  args: [c]\n").expect("valid patch");
+        assert_eq!(value["spec"]["args"], yaml("[c]"));
+    }
+
+    #[test]
+    fn delete_directive_removes_list_elements_and_keys() {
+        let patch = "
+metadata:
+  name: app
+  $patch: merge
+spec:
+  template:
+    spec:
+      $patch: merge
+      containers:
+      - name: sidecar
+        $patch: delete
+      - name: missing
+        $patch: delete
+  strategy:
+    $patch: delete
+";
+        let value = merged(&format!("{DEPLOYMENT}  strategy: {{}}\n"), patch).expect("valid patch");
+        let containers = &value["spec"]["template"]["spec"]["containers"];
+        assert_eq!(containers.as_sequence().map(Vec::len), Some(1));
+        assert_eq!(containers[0]["name"], yaml("app"));
+        assert!(value["spec"].get("strategy").is_none());
+    }
+
+    #[test]
+    fn delete_directive_cannot_delete_the_resource() {
+        let err = merged(DEPLOYMENT, "$patch: delete\n").err();
+        assert!(matches!(
+            err,
+            Some(RenderError::Transform(TransformError::Directive(directive))) if directive == "delete"
+        ));
+    }
+
+    #[test]
+    fn metadata_that_is_no_mapping_is_an_error() {
+        let mut resource = resource("kind: ConfigMap\nmetadata: app\n");
+        let err = Transformer::NamePrefix {
+            target: Target::default(),
+            prefix: "prod-".to_string(),
+        }
+        .apply(&mut resource)
+        .err();
+        assert!(matches!(err, Some(RenderError::Mapping(err)) if err.path == "metadata"));
+    }
+}
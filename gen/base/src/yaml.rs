@@ -0,0 +1,334 @@
+//! Canonical YAML emitter used for every generated file.
+//!
+//! Mapping keys are ordered deterministically (well known keys first, the rest
+//! alphabetically), indentation is always two spaces with sequences aligned to
+//! their parent key, and strings are only left unquoted if no YAML 1.1 or 1.2
+//! parser could read them as anything else.
+
+use std::fmt::Display;
+
+use serde_yaml::Value;
+
+use crate::diff;
+
+/// Keys that come first, in this order, wherever they appear.
+const PRIORITY: [&str; 5] = ["apiVersion", "kind", "metadata", "name", "namespace"];
+
+pub fn to_string(value: &Value) -> String {
+    to_annotated_string(value, &|_| None)
+}
+
+/// Like [`to_string`], with `note(path)` appended as comment to the line of
+/// every scalar. Paths are the ones of [`diff::Field`].
+pub fn to_annotated_string(value: &Value, note: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut out = String::new();
+    emit(&mut out, value, 0, "", note);
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+/// Double quoted YAML string, for interpolating free-form values into
+/// templates so they survive parsing as strings.
+pub fn quote(value: impl Display) -> String {
+    let value = value.to_string();
+    let mut res = String::with_capacity(value.len() + 2);
+    res.push('"');
+    value.chars().for_each(|char| match char {
+        '"' => res.push_str("\\\""),
+        '\\' => res.push_str("\\\\"),
+        '\n' => res.push_str("\\n"),
+        '\t' => res.push_str("\\t"),
+        '\r' => res.push_str("\\r"),
+        char if char.is_control() => res.push_str(&format!("\\u{:04x}", char as u32)),
+        char => res.push(char),
+    });
+    res.push('"');
+    res
+}
+
+fn rank(key: &Value) -> (usize, String) {
+    let key = key.as_str().map_or_else(|| scalar(key), str::to_string);
+    let rank = PRIORITY
+        .iter()
+        .position(|priority| *priority == key)
+        .unwrap_or(PRIORITY.len());
+    (rank, key)
+}
+
+fn is_collection(value: &Value) -> bool {
+    match value {
+        Value::Mapping(mapping) => !mapping.is_empty(),
+        Value::Sequence(sequence) => !sequence.is_empty(),
+        Value::Tagged(tagged) => is_collection(&tagged.value),
+        _ => false,
+    }
+}
+
+/// Appends the comment for `path` to the current line.
+fn comment(out: &mut String, path: &str, note: &dyn Fn(&str) -> Option<String>) {
+    if let Some(note) = note(path) {
+        out.push_str("  # ");
+        out.push_str(&note);
+    }
+}
+
+fn emit(
+    out: &mut String,
+    value: &Value,
+    indent: usize,
+    path: &str,
+    note: &dyn Fn(&str) -> Option<String>,
+) {
+    match value {
+        Value::Mapping(mapping) if !mapping.is_empty() => {
+            let mut entries = mapping.iter().collect::<Vec<_>>();
+            entries.sort_by_cached_key(|(key, _)| rank(key));
+            entries
+                .into_iter()
+                .enumerate()
+                .for_each(|(i, (key, value))| {
+                    if i > 0 {
+                        out.push_str(&" ".repeat(indent));
+                    }
+                    let path = diff::key_path(path, key);
+                    out.push_str(&scalar(key));
+                    out.push(':');
+                    match value {
+                        Value::Mapping(_) if is_collection(value) => {
+                            out.push('\n');
+                            out.push_str(&" ".repeat(indent + 2));
+                            emit(out, value, indent + 2, &path, note);
+                        }
+                        Value::Sequence(_) if is_collection(value) => {
+                            out.push('\n');
+                            out.push_str(&" ".repeat(indent));
+                            emit(out, value, indent, &path, note);
+                        }
+                        value => {
+                            out.push(' ');
+                            emit(out, value, indent + 2, &path, note);
+                        }
+                    }
+                });
+        }
+        Value::Sequence(sequence) if !sequence.is_empty() => {
+            sequence.iter().enumerate().for_each(|(i, item)| {
+                if i > 0 {
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str("- ");
+                emit(
+                    out,
+                    item,
+                    indent + 2,
+                    &diff::item_path(path, sequence, i),
+                    note,
+                );
+            });
+        }
+        Value::Mapping(_) | Value::Sequence(_) => {
+            out.push_str(if value.is_mapping() { "{}" } else { "[]" });
+            comment(out, path, note);
+            out.push('\n');
+        }
+        Value::Tagged(tagged) => emit(out, &tagged.value, indent, path, note),
+        Value::String(string) if string.contains('\n') && block_safe(string) => {
+            let (chomping, body) = match string.strip_suffix('\n') {
+                Some(body) => ("", body),
+                None => ("-", string.as_str()),
+            };
+            out.push('|');
+            out.push_str(chomping);
+            comment(out, path, note);
+            out.push('\n');
+            body.split('\n').for_each(|line| {
+                if !line.is_empty() {
+                    out.push_str(&" ".repeat(indent));
+                    out.push_str(line);
+                }
+                out.push('\n');
+            });
+        }
+        value => {
+            out.push_str(&scalar(value));
+            comment(out, path, note);
+            out.push('\n');
+        }
+    }
+}
+
+/// Literal blocks can represent the string exactly.
+fn block_safe(string: &str) -> bool {
+    !string.starts_with([' ', '\n'])
+        && !string.ends_with("\n\n")
+        && string
+            .split('\n')
+            .all(|line| !line.ends_with([' ', '\t']) && !line.chars().any(char::is_control))
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(bool) => bool.to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::String(string) if plain(string) => string.clone(),
+        Value::String(string) => quote(string),
+        Value::Tagged(tagged) => scalar(&tagged.value),
+        Value::Mapping(_) | Value::Sequence(_) => unreachable!("collections are not scalars"),
+    }
+}
+
+/// Whether the string reads back as the same string without quotes.
+fn plain(string: &str) -> bool {
+    const INDICATORS: &[char] = &[
+        '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%', '@',
+        '`',
+    ];
+
+    let Some(first) = string.chars().next() else {
+        return false;
+    };
+    let indicator = match first {
+        '-' | '?' | ':' => !safe_indicator(string),
+        first => INDICATORS.contains(&first),
+    };
+
+    !indicator
+        && !string.starts_with(char::is_whitespace)
+        && !string.ends_with(char::is_whitespace)
+        && !string.ends_with(':')
+        && !string.contains(": ")
+        && !string.contains(" #")
+        && !string.chars().any(|char| char.is_control())
+        && !ambiguous(string)
+}
+
+/// `-`, `?` and `:` only start a plain scalar if followed by a safe character.
+fn safe_indicator(string: &str) -> bool {
+    string
+        .chars()
+        .nth(1)
+        .is_some_and(|char| !char.is_whitespace() && !",[]{}".contains(char))
+}
+
+/// Strings a YAML 1.1 or 1.2 parser would resolve to null, a bool, a number or
+/// a timestamp.
+fn ambiguous(string: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "~", "null", "y", "yes", "n", "no", "true", "false", "on", "off", ".inf", "-.inf", "+.inf",
+        ".nan", "<<", "=",
+    ];
+
+    let lower = string.to_lowercase();
+    if KEYWORDS.contains(&lower.as_str()) {
+        return true;
+    }
+
+    let digits = lower.trim_start_matches(['-', '+']).replace('_', "");
+    let numeric = |string: &str, radix: u32| {
+        !string.is_empty() && string.chars().all(|char| char.is_digit(radix))
+    };
+
+    numeric(&digits, 10)
+        || digits
+            .strip_prefix("0x")
+            .is_some_and(|hex| numeric(hex, 16))
+        || digits.strip_prefix("0o").is_some_and(|oct| numeric(oct, 8))
+        || digits.strip_prefix("0b").is_some_and(|bin| numeric(bin, 2))
+        || digits.parse::<f64>().is_ok()
+        || (digits.contains(':') && digits.split(':').all(|part| numeric(part, 10)))
+        || timestamp(&lower)
+}
+
+fn timestamp(string: &str) -> bool {
+    let date = string.get(..10).unwrap_or_default();
+    date.len() == 10
+        && date.char_indices().all(|(i, char)| match i {
+            4 | 7 => char == '-',
+            _ => char.is_ascii_digit(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emitted(yaml: &str) -> String {
+        to_string(&serde_yaml::from_str(yaml).expect("valid yaml"))
+    }
+
+    #[test]
+    fn ambiguous_scalars_are_quoted() {
+        [
+            "yes",
+            "No",
+            "on",
+            "1.0",
+            "10",
+            "0x1f",
+            "1_000",
+            "null",
+            "~",
+            "12:30",
+            "2024-01-01",
+        ]
+        .into_iter()
+        .for_each(|string| {
+            assert_eq!(scalar(&Value::from(string)), format!("\"{string}\""));
+        });
+    }
+
+    #[test]
+    fn plain_strings_stay_unquoted() {
+        ["app", "nginx:1.27", "v1.0", "-x", "a-b", "/api"]
+            .into_iter()
+            .for_each(|string| assert_eq!(scalar(&Value::from(string)), string));
+    }
+
+    #[test]
+    fn quoted_strings_read_back_as_strings() {
+        ["yes", "1.0", "null", "a: b", "# x", " x", "x\ty"]
+            .into_iter()
+            .for_each(|string| {
+                let value = serde_yaml::from_str::<Value>(&scalar(&Value::from(string)));
+                assert_eq!(value.ok(), Some(Value::from(string)));
+            });
+    }
+
+    #[test]
+    fn well_known_keys_come_first() {
+        let yaml = emitted(
+            "
+spec: {}
+zeta: 1
+metadata:
+  namespace: apps
+  labels: {b: '2', a: '1'}
+  name: app
+kind: Service
+apiVersion: v1
+",
+        );
+        let expected = "\
+apiVersion: v1
+kind: Service
+metadata:
+  name: app
+  namespace: apps
+  labels:
+    a: \"1\"
+    b: \"2\"
+spec: {}
+zeta: 1
+";
+        assert_eq!(yaml, expected);
+    }
+
+    #[test]
+    fn sequences_align_with_their_key() {
+        let yaml = emitted("ports: [{name: http, port: 80}]\nargs: [a, b]\n");
+        assert_eq!(yaml, "args:\n- a\n- b\nports:\n- name: http\n  port: 80\n");
+    }
+}
@@ -1,40 +1,37 @@
-use timpl_gitops_demo_gen_base::*;
+use std::collections::BTreeMap;
 
-fn default_manifest() -> Manifest {
+use timpl_gitops_demo_gen_base::{
+    data::*,
+    delivery::*,
+    ingress::{IngressBackend, IngressParams},
+    logging::*,
+    monitoring::PrometheusParams,
+    slo::*,
+    transform::*,
+    *,
+};
+
+/// Manifest every stage starts from, also the defaults of the schema.
+pub fn default_manifest() -> Manifest {
     Manifest {
         apps: Apps {
-            frontend: Frontend {
+            frontend: AppConfig {
                 enabled: true,
-                replicas: 1,
-                image: Image {
-                    reference: ImageRef {
-                        registry: "cr.example.com".to_string(),
-                        name: "frontend".to_string(),
-                        tag: "latest".to_string(),
-                    },
-                    pull_policy: PullPolicy::Always,
-                },
-                service_type: ServiceType::ClusterIP,
+                delivery: Delivery::Standard,
             },
-            backend: Backend {
+            backend: AppConfig {
                 enabled: true,
-                replicas: 1,
-                image: Image {
-                    reference: ImageRef {
-                        registry: "cr.example.com".to_string(),
-                        name: "backend".to_string(),
-                        tag: "latest".to_string(),
-                    },
-                    pull_policy: PullPolicy::Always,
-                },
-                service_type: ServiceType::ClusterIP,
+                delivery: Delivery::Standard,
             },
         },
+        data: Data {
+            postgres: Datastore::InCluster,
+            redis: Datastore::InCluster,
+        },
         infrastructure: Infrastructure {
             ingress: Ingress {
                 enabled: true,
                 domains: vec![],
-                tls: true,
             },
             monitoring: Monitoring {
                 enabled: true,
@@ -43,12 +40,95 @@ fn default_manifest() -> Manifest {
                     logging: true,
                     metrics: true,
                 },
-                dashboard: Dashboard {
-                    enabled: true,
-                    service_type: ServiceType::ClusterIP,
+                alerting: Alerting {
+                    escalation: Escalation::Notify,
                 },
+                tracing: Tracing {
+                    sampling_ratio: 0.1,
+                },
+                dashboard: Dashboard { enabled: true },
+            },
+        },
+    }
+}
+
+/// Frontend running `replicas` of the image tagged `tag`, with the
+/// objectives of every stage.
+fn frontend(tag: &str, replicas: u16) -> AppParams {
+    AppParams {
+        replicas,
+        tag: tag.to_string(),
+        slos: vec![
+            Slo {
+                name: "availability".to_string(),
+                indicator: Indicator::Availability,
+                objective: 0.995,
+                window: 30,
+            },
+            Slo {
+                name: "latency".to_string(),
+                indicator: Indicator::Latency { threshold: 0.5 },
+                objective: 0.99,
+                window: 30,
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+/// Backend running `replicas` of the image tagged `tag`, with the
+/// objectives of every stage.
+fn backend(tag: &str, replicas: u16) -> AppParams {
+    AppParams {
+        replicas,
+        tag: tag.to_string(),
+        slos: vec![
+            Slo {
+                name: "availability".to_string(),
+                indicator: Indicator::Availability,
+                objective: 0.999,
+                window: 30,
             },
+            Slo {
+                name: "latency".to_string(),
+                indicator: Indicator::Latency { threshold: 0.25 },
+                objective: 0.99,
+                window: 30,
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+/// Small PostgreSQL without a volume, reset on every restart.
+fn ephemeral_postgres() -> Postgres {
+    Postgres {
+        size: Size {
+            cpu: "100m".to_string(),
+            memory: "128Mi".to_string(),
         },
+        persistence: None,
+        backup: None,
+    }
+}
+
+/// Small Redis without a volume, reset on every restart.
+fn ephemeral_redis() -> Redis {
+    Redis {
+        size: Size {
+            cpu: "50m".to_string(),
+            memory: "64Mi".to_string(),
+        },
+        persistence: None,
+    }
+}
+
+/// NGINX Ingress Controller installed with the cluster config, serving the
+/// domains with TLS.
+fn nginx_ingress() -> IngressParams {
+    IngressParams {
+        backend: IngressBackend::Nginx { plus: false },
+        tls: true,
     }
 }
 
@@ -58,81 +138,200 @@ pub(super) mod clusters {
     pub(super) fn prod() -> ClusterConfig {
         ClusterConfig {
             stage: Stage::Prod,
+            cluster: "prod".to_string(),
             manifest: {
                 let mut manifest = default_manifest();
-                manifest.apps.frontend.replicas = 3;
-                manifest.apps.frontend.image.reference.tag = "prod-latest".to_string();
-                manifest.apps.backend.replicas = 3;
-                manifest.apps.backend.image.reference.tag = "prod-latest".to_string();
+                manifest.infrastructure.monitoring.tracing.sampling_ratio = 0.01;
+                manifest.infrastructure.monitoring.alerting.escalation = Escalation::Page;
+                manifest.data.postgres = Datastore::External(Endpoint {
+                    host: "backend.postgres.example.com".to_string(),
+                    port: 5432,
+                    secret: SecretRef {
+                        name: "backend-postgres".to_string(),
+                        key: "password".to_string(),
+                    },
+                });
+                manifest.data.redis = Datastore::External(Endpoint {
+                    host: "backend.redis.example.com".to_string(),
+                    port: 6379,
+                    secret: SecretRef {
+                        name: "backend-redis".to_string(),
+                        key: "password".to_string(),
+                    },
+                });
                 manifest.infrastructure.ingress.domains = vec![
                     "prod.app.example.com".to_string(),
                     "app.example.com".to_string(),
                 ];
                 manifest
             },
+            transformers: vec![Transformer::StrategicMerge {
+                target: Target {
+                    kind: Some("Deployment".to_string()),
+                    ..Default::default()
+                },
+                patch: r#"
+                    spec:
+                      strategy:
+                        rollingUpdate:
+                          maxUnavailable: 0
+                "#
+                .to_string(),
+            }],
+            plugins: vec![],
+            params: BTreeMap::from([
+                params::block(&FrontendPackage {}, &frontend("prod-latest", 3)),
+                params::block(&BackendPackage {}, &backend("prod-latest", 3)),
+                params::block(&IngressConfigPackage {}, &nginx_ingress()),
+                params::block(
+                    &PrometheusPackage {},
+                    &PrometheusParams { retention_days: 30 },
+                ),
+                params::block(
+                    &LokiPackage {},
+                    &LokiParams {
+                        retention_days: 30,
+                        storage: LogStorage::S3 {
+                            endpoint: "s3.eu-central-1.amazonaws.com".to_string(),
+                            region: "eu-central-1".to_string(),
+                            bucket: "app-prod-logs".to_string(),
+                            secret: "loki-s3".to_string(),
+                        },
+                    },
+                ),
+                params::block(&PromtailPackage {}, &{
+                    let mut params = PromtailParams::default();
+                    params.filters.push(LevelFilter {
+                        namespace: "default".to_string(),
+                        level: Level::Info,
+                    });
+                    params
+                }),
+            ]),
         }
     }
 
     pub(super) fn dev() -> ClusterConfig {
         ClusterConfig {
             stage: Stage::Dev,
+            cluster: "dev".to_string(),
             manifest: {
                 let mut manifest = default_manifest();
-                manifest.apps.frontend.replicas = 2;
-                manifest.apps.frontend.image.reference.tag = "dev-latest".to_string();
-                manifest.apps.backend.replicas = 2;
-                manifest.apps.backend.image.reference.tag = "dev-latest".to_string();
+                manifest.infrastructure.monitoring.tracing.sampling_ratio = 0.5;
                 manifest.infrastructure.ingress.domains = vec!["dev.app.example.com".to_string()];
                 manifest
             },
+            transformers: vec![],
+            plugins: vec![],
+            params: BTreeMap::from([
+                params::block(&FrontendPackage {}, &frontend("dev-latest", 2)),
+                params::block(&BackendPackage {}, &backend("dev-latest", 2)),
+                params::block(
+                    &PostgresPackage {},
+                    &Postgres {
+                        size: Size {
+                            cpu: "500m".to_string(),
+                            memory: "512Mi".to_string(),
+                        },
+                        persistence: Some("16Gi".to_string()),
+                        backup: Some(Backup {
+                            schedule: "@daily".to_string(),
+                            storage: "16Gi".to_string(),
+                        }),
+                    },
+                ),
+                params::block(&IngressConfigPackage {}, &nginx_ingress()),
+                params::block(
+                    &LokiPackage {},
+                    &LokiParams {
+                        retention_days: 14,
+                        ..Default::default()
+                    },
+                ),
+            ]),
         }
     }
 
     pub(super) fn test() -> ClusterConfig {
         ClusterConfig {
             stage: Stage::Test,
+            cluster: "test".to_string(),
             manifest: {
                 let mut manifest = default_manifest();
-                manifest.apps.frontend.replicas = 1;
-                manifest.apps.frontend.image.reference.tag = "test-latest".to_string();
-                manifest.apps.backend.replicas = 1;
-                manifest.apps.backend.image.reference.tag = "test-latest".to_string();
+                manifest.infrastructure.monitoring.tracing.sampling_ratio = 1.0;
                 manifest.infrastructure.ingress.domains = vec!["test.app.example.com".to_string()];
                 manifest
             },
+            transformers: vec![],
+            plugins: vec![],
+            params: BTreeMap::from([
+                params::block(&FrontendPackage {}, &frontend("test-latest", 1)),
+                params::block(&BackendPackage {}, &backend("test-latest", 1)),
+                params::block(&IngressConfigPackage {}, &nginx_ingress()),
+            ]),
         }
     }
 
     pub(super) fn local() -> ClusterConfig {
         ClusterConfig {
             stage: Stage::Local,
+            cluster: "local".to_string(),
             manifest: {
                 let mut manifest = default_manifest();
-                manifest.apps.frontend.replicas = 1;
-                manifest.apps.frontend.image.reference.tag = "local-latest".to_string();
-                manifest.apps.backend.replicas = 1;
-                manifest.apps.backend.image.reference.tag = "local-latest".to_string();
+                manifest.infrastructure.monitoring.tracing.sampling_ratio = 1.0;
                 manifest.infrastructure.ingress.domains = vec!["localhost".to_string()];
-                manifest.infrastructure.ingress.tls = false;
                 manifest
             },
+            transformers: vec![],
+            plugins: vec![],
+            params: BTreeMap::from([
+                params::block(&FrontendPackage {}, &frontend("local-latest", 1)),
+                params::block(&BackendPackage {}, &backend("local-latest", 1)),
+                params::block(&PostgresPackage {}, &ephemeral_postgres()),
+                params::block(&RedisPackage {}, &ephemeral_redis()),
+                params::block(
+                    &IngressConfigPackage {},
+                    &IngressParams {
+                        tls: false,
+                        ..Default::default()
+                    },
+                ),
+                params::block(
+                    &LokiPackage {},
+                    &LokiParams {
+                        retention_days: 1,
+                        ..Default::default()
+                    },
+                ),
+            ]),
         }
     }
 
     pub(super) fn minimal() -> ClusterConfig {
         ClusterConfig {
             stage: Stage::Minimal,
+            cluster: "minimal".to_string(),
             manifest: {
                 let mut manifest = default_manifest();
-                manifest.apps.frontend.replicas = 1;
-                manifest.apps.frontend.image.reference.tag = "minimal-latest".to_string();
-                manifest.apps.backend.replicas = 1;
-                manifest.apps.backend.image.reference.tag = "minimal-latest".to_string();
                 manifest.infrastructure.ingress.domains = vec!["localhost".to_string()];
-                manifest.infrastructure.ingress.tls = false;
                 manifest.infrastructure.monitoring.enabled = false;
                 manifest
             },
+            transformers: vec![],
+            plugins: vec![],
+            params: BTreeMap::from([
+                params::block(&FrontendPackage {}, &frontend("minimal-latest", 1)),
+                params::block(&BackendPackage {}, &backend("minimal-latest", 1)),
+                params::block(&PostgresPackage {}, &ephemeral_postgres()),
+                params::block(&RedisPackage {}, &ephemeral_redis()),
+                params::block(
+                    &IngressConfigPackage {},
+                    &IngressParams {
+                        tls: false,
+                        ..Default::default()
+                    },
+                ),
+            ]),
         }
     }
 }
@@ -142,21 +341,124 @@ fn clusters() -> Vec<ClusterConfig> {
     vec![prod(), dev(), test(), local(), minimal()]
 }
 
-fn packages() -> Vec<Box<dyn Package>> {
+fn dashboard(config: &ClusterConfig) -> bool {
+    let monitoring = &config.manifest.infrastructure.monitoring;
+    monitoring.enabled && monitoring.dashboard.enabled
+}
+
+/// Whether the domains are served with certificates, issued by cert-manager.
+fn certificates(config: &ClusterConfig) -> bool {
+    config.manifest.infrastructure.ingress.enabled
+        && params::of(&CertIssuerPackage {}, config).is_ok_and(|params| params.tls)
+}
+
+pub(super) fn packages() -> Vec<Registration> {
     vec![
-        Box::new(FrontendPackage {}),
-        Box::new(BackendPackage {}),
-        Box::new(IngressSystemPackage {}),
-        Box::new(IngressConfigPackage {}),
+        Registration {
+            package: Box::new(FrontendPackage {}),
+            rules: vec![Rule::When(|config| config.manifest.apps.frontend.enabled)],
+        },
+        Registration {
+            package: Box::new(BackendPackage {}),
+            rules: vec![Rule::When(|config| config.manifest.apps.backend.enabled)],
+        },
+        Registration {
+            package: Box::new(PostgresPackage {}),
+            rules: vec![Rule::When(|config| {
+                config.manifest.apps.backend.enabled && config.manifest.data.postgres.in_cluster()
+            })],
+        },
+        Registration {
+            package: Box::new(RedisPackage {}),
+            rules: vec![Rule::When(|config| {
+                config.manifest.apps.backend.enabled && config.manifest.data.redis.in_cluster()
+            })],
+        },
+        Registration {
+            package: Box::new(PrometheusPackage {}),
+            rules: vec![Rule::When(monitoring::metrics)],
+        },
+        Registration {
+            package: Box::new(LokiPackage {}),
+            rules: vec![Rule::When(monitoring::logging)],
+        },
+        Registration {
+            package: Box::new(PromtailPackage {}),
+            rules: vec![Rule::When(monitoring::logging)],
+        },
+        Registration {
+            package: Box::new(TempoPackage {}),
+            rules: vec![Rule::When(monitoring::tracing)],
+        },
+        Registration {
+            package: Box::new(CollectorPackage {}),
+            rules: vec![Rule::When(monitoring::tracing)],
+        },
+        Registration {
+            package: Box::new(GrafanaOperatorPackage {}),
+            rules: vec![Rule::When(dashboard)],
+        },
+        Registration {
+            package: Box::new(GrafanaPackage {}),
+            rules: vec![Rule::When(dashboard)],
+        },
+        Registration {
+            package: Box::new(IngressSystemPackage {}),
+            rules: vec![
+                Rule::When(|config| config.manifest.infrastructure.ingress.enabled),
+                // skipped if the cluster comes with its own ingress controller
+                Rule::When(|config| {
+                    params::of(&IngressSystemPackage {}, config).is_ok_and(|params| {
+                        !params
+                            .backend
+                            .backend()
+                            .system(&params::Facts::of(config))
+                            .is_empty()
+                    })
+                }),
+            ],
+        },
+        Registration {
+            package: Box::new(CertManagerPackage {}),
+            rules: vec![Rule::When(certificates)],
+        },
+        Registration {
+            package: Box::new(CertIssuerPackage {}),
+            rules: vec![Rule::When(certificates)],
+        },
+        Registration {
+            package: Box::new(IngressConfigPackage {}),
+            rules: vec![Rule::When(|config| {
+                config.manifest.infrastructure.ingress.enabled
+            })],
+        },
     ]
 }
 
 pub fn deployments() -> Vec<Deployment> {
+    let builtin = packages()
+        .iter()
+        .flat_map(|registration| {
+            let package = &registration.package;
+            [Some(package.name()), package.params_block()]
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+
     clusters()
         .iter()
         .map(|config| Deployment {
             config: config.clone(),
-            packages: packages(),
+            packages: packages()
+                .into_iter()
+                .filter(|registration| registration.applies(config))
+                .map(|registration| registration.package)
+                .chain(plugin::packages(
+                    std::path::Path::new(super::PLUGINS),
+                    &config.plugins,
+                    &builtin,
+                ))
+                .collect(),
         })
         .collect()
 }
@@ -1,25 +1,40 @@
-mod config;
+pub mod config;
 
-pub fn build() {
-    let folder = "clusters";
-    let deployments = config::deployments();
-    let files = deployments.iter().map(|deployment| {
-        let path = std::path::Path::new(folder)
-            .join(deployment.config.stage.to_string())
-            .join("resources.yaml");
-        let contents = deployment.resources().join("\n---\n");
-        (path, contents)
-    });
+use timpl_gitops_demo_gen_base::{
+    diagnostics::{Diagnostic, Diagnostics},
+    fleet::{self, RenderedFleet},
+    output::{self, Writer},
+    schema,
+};
 
-    let _ = std::fs::remove_dir_all(folder);
+/// Directory of the stages, reconciled by Flux.
+pub const FOLDER: &str = "clusters";
 
-    files.for_each(|(path, contents)| {
-        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-        std::fs::write(path, {
-            let mut res = contents;
-            res.push('\n');
-            res
-        })
-        .unwrap();
-    });
+/// Directory of the WebAssembly modules of plugin packages.
+pub const PLUGINS: &str = "plugins";
+
+/// Schema of the cluster configs, with the parameter blocks of every
+/// registered package.
+pub fn schema() -> serde_json::Value {
+    let registrations = config::packages();
+    let packages = registrations
+        .iter()
+        .map(|registration| registration.package.as_ref())
+        .collect::<Vec<_>>();
+    schema::config(&config::default_manifest(), &packages)
+}
+
+pub fn render() -> Result<RenderedFleet, Diagnostics> {
+    fleet::render(&config::deployments(), &schema(), &format!("./{FOLDER}"))
+}
+
+/// Renders all stages into [`FOLDER`].
+pub fn build() -> Result<RenderedFleet, Diagnostics> {
+    let fleet = render()?;
+    output::Filesystem {
+        root: FOLDER.into(),
+    }
+    .write(&output::files(&fleet))
+    .map_err(|err| Diagnostics(vec![Diagnostic::error(format!("writing {FOLDER}: {err}"))]))?;
+    Ok(fleet)
 }
@@ -1,6 +1,154 @@
 #[path ="lib.rs"]
 mod lib;
 
-fn main() {
-    lib::build();
+use std::process::ExitCode;
+
+use timpl_gitops_demo_gen_base::{
+    diagnostics::Diagnostics,
+    diff,
+    fleet::RenderedFleet,
+    output::{self, Writer},
+    plugin, provenance, schema, Deployment, Stage,
+};
+
+const USAGE: &str = "usage: timpl-gitops-demo [render (--stdout | --archive <file>) \
+    | diff-stages <stage> <stage> | explain <stage> | schema | validate <file>... | plugins]";
+
+/// Prints the warnings of a rendered fleet, or all diagnostics if rendering
+/// failed.
+fn report(result: Result<RenderedFleet, Diagnostics>) -> Result<RenderedFleet, String> {
+    let fleet = result.map_err(|diagnostics| diagnostics.to_string())?;
+    fleet
+        .stages
+        .iter()
+        .flat_map(|stage| &stage.diagnostics)
+        .for_each(|diagnostic| eprintln!("{diagnostic}"));
+    Ok(fleet)
+}
+
+/// Renders all stages into `writer`.
+fn render(writer: &mut dyn Writer) -> Result<(), String> {
+    let fleet = report(lib::render())?;
+    writer
+        .write(&output::files(&fleet))
+        .map_err(|err| err.to_string())
+}
+
+fn deployment<'a>(deployments: &'a [Deployment], name: &str) -> Result<&'a Deployment, String> {
+    let stage = name.parse::<Stage>().map_err(|err| err.to_string())?;
+    deployments
+        .iter()
+        .find(|deployment| deployment.config.stage == stage)
+        .ok_or_else(|| format!("stage {stage} is not deployed"))
+}
+
+/// Prints the differences of the configs and rendered resources of two
+/// stages, failing if there are any.
+fn diff_stages(before: &str, after: &str) -> Result<bool, String> {
+    let deployments = lib::config::deployments();
+    let before = deployment(&deployments, before)?;
+    let after = deployment(&deployments, after)?;
+    let resources = |deployment: &Deployment| deployment.resources().map_err(|err| err.to_string());
+
+    let diff = diff::stages(
+        (&before.config, &resources(before)?),
+        (&after.config, &resources(after)?),
+    );
+    print!("{diff}");
+    Ok(diff.is_empty())
+}
+
+/// Prints the rendered resources of a stage, annotated with the package and
+/// the config values they come from.
+fn explain(stage: &str) -> Result<(), String> {
+    let deployments = lib::config::deployments();
+    let explained = provenance::explain(
+        deployment(&deployments, stage)?,
+        &lib::config::default_manifest(),
+    )
+    .map_err(|err| err.to_string())?;
+    let yaml = explained
+        .iter()
+        .map(provenance::Explained::to_yaml)
+        .collect::<Vec<_>>();
+    print!("{}", yaml.join("---\n"));
+    Ok(())
+}
+
+/// Prints the JSON schema of cluster config files.
+fn print_schema() -> Result<(), String> {
+    let schema = serde_json::to_string_pretty(&lib::schema()).map_err(|err| err.to_string())?;
+    println!("{schema}");
+    Ok(())
+}
+
+/// Checks cluster config files against the schema of the config.
+fn validate(files: &[&str]) -> Result<(), String> {
+    let schema = lib::schema();
+    let errors = files
+        .iter()
+        .filter_map(|file| {
+            let source = std::fs::read_to_string(file).map_err(|err| err.to_string());
+            let config = source
+                .and_then(|source| schema::load(&schema, &source).map_err(|err| err.to_string()));
+            config.err().map(|err| format!("{file}: {err}"))
+        })
+        .collect::<Vec<_>>();
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join("\n")),
+    }
+}
+
+/// Prints the name and hash of every plugin module, as pinned in a cluster
+/// config.
+fn plugins() -> Result<(), String> {
+    let plugins = plugin::discover(lib::PLUGINS.as_ref())
+        .map_err(|err| format!("{}: {err}", lib::PLUGINS))?;
+    plugins
+        .iter()
+        .for_each(|(name, sha256)| println!("{name} {sha256}"));
+    Ok(())
+}
+
+fn exit(result: Result<(), String>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    match args[..] {
+        [] => exit(report(lib::build()).map(|_| ())),
+        ["render", "--stdout"] => exit(render(&mut output::Stdout)),
+        ["render", "--archive", file] => exit(
+            std::fs::File::create(file)
+                .map_err(|err| format!("{file}: {err}"))
+                .and_then(|out| render(&mut output::Archive { out })),
+        ),
+        ["diff-stages", before, after] => match diff_stages(before, after) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(err) => {
+                eprintln!("{err}");
+                ExitCode::from(2)
+            }
+        },
+        ["explain", stage] => exit(explain(stage)),
+        ["schema"] => exit(print_schema()),
+        ["validate", ref files @ ..] if !files.is_empty() => exit(validate(files)),
+        ["plugins"] => exit(plugins()),
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
 }